@@ -0,0 +1,189 @@
+// Analysis looks for loops and moves that are probably mistakes without running
+// the program, it works on the output of Program::compile like the optimizer.
+
+use std::fmt;
+
+use command::Command;
+
+// Warning is something in a program that is likely a mistake, the loops are
+// given as the indexes of their brackets in the commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Warning {
+    // the loop is only ever reached with a zero cell so its body never runs
+    DeadLoop { start: usize, end: usize },
+    // the loop has no body so it never ends once it is entered
+    EmptyLoop { start: usize },
+    // the pointer is moved past the end of the tape before any loop
+    TapeOverflow { at: usize },
+}
+
+impl Warning {
+    // start is the index of the '[' the warning is about, or of the move
+    // for a tape overflow
+    pub fn start(&self) -> usize {
+        match *self {
+            Warning::DeadLoop { start, .. } | Warning::EmptyLoop { start } => start,
+            Warning::TapeOverflow { at } => at,
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match *self {
+            Warning::DeadLoop { .. } => "loop is never entered",
+            Warning::EmptyLoop { .. } => "empty loop never ends once entered",
+            Warning::TapeOverflow { .. } => "pointer moves past the end of the tape",
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at command {}", self.message(), self.start())
+    }
+}
+
+// check finds the dead and empty loops in commands. A cell is only known to be
+// zero before anything has been written to the tape, right after a loop ends
+// and after a Clear, so dead loops are ones in those places. A program with an
+// unmatched bracket gives no warnings at all, validate reports the bracket.
+pub fn check(commands: &[Command]) -> Vec<Warning> {
+    let ends = match matching_ends(commands) {
+        Some(ends) => ends,
+        None => return Vec::new(),
+    };
+
+    let mut warnings = Vec::new();
+    // untouched is set while every cell is still zero
+    let mut untouched = true;
+    let mut zero = true;
+    let mut pc = 0;
+    while pc < commands.len() {
+        match commands[pc] {
+            Command::JmpFwd(_) if zero => {
+                warnings.push(Warning::DeadLoop { start: pc, end: ends[pc] });
+                pc = ends[pc];
+            },
+            Command::JmpFwd(_) => {
+                if ends[pc] == pc + 1 {
+                    warnings.push(Warning::EmptyLoop { start: pc });
+                }
+                // the body may run any number of times
+                untouched = false;
+                zero = false;
+            },
+            Command::JmpBack(_) | Command::Clear | Command::MulAdd(_) => zero = true,
+            Command::Right | Command::Left | Command::Move(_) => zero = untouched,
+            Command::Inc | Command::Dec | Command::Add(_) | Command::In => {
+                untouched = false;
+                zero = false;
+            },
+            Command::Out | Command::Debug => {},
+        }
+        pc += 1;
+    }
+
+    warnings
+}
+
+// remove_dead_loops drops every loop check finds is never entered, which
+// doesn't change what the program does
+pub fn remove_dead_loops(commands: Vec<Command>) -> Vec<Command> {
+    let dead: Vec<(usize, usize)> = check(&commands).into_iter().filter_map(|warning| match warning {
+        Warning::DeadLoop { start, end } => Some((start, end)),
+        _ => None,
+    }).collect();
+
+    commands.into_iter().enumerate()
+        .filter(|&(pc, _)| !dead.iter().any(|&(start, end)| start <= pc && pc <= end))
+        .map(|(_, command)| command)
+        .collect()
+}
+
+// check_tape warns when the commands before the first loop move the pointer
+// past the end of a fixed tape of tape_size cells. Moves inside loops can't be
+// followed without running the program so they are not checked.
+pub fn check_tape(commands: &[Command], tape_size: usize) -> Option<Warning> {
+    let mut ptr: isize = 0;
+    for (pc, command) in commands.iter().enumerate() {
+        ptr += match *command {
+            Command::Right => 1,
+            Command::Left => -1,
+            Command::Move(delta) => delta,
+            Command::JmpFwd(_) | Command::JmpBack(_) => return None,
+            _ => 0,
+        };
+        if ptr >= tape_size as isize {
+            return Some(Warning::TapeOverflow { at: pc });
+        }
+    }
+
+    None
+}
+
+// matching_ends gives the index of the matching ']' for each '[', None if
+// there is an unmatched bracket
+fn matching_ends(commands: &[Command]) -> Option<Vec<usize>> {
+    let mut ends = vec![0; commands.len()];
+    let mut open = Vec::new();
+    for (pc, command) in commands.iter().enumerate() {
+        match *command {
+            Command::JmpFwd(_) => open.push(pc),
+            Command::JmpBack(_) => ends[open.pop()?] = pc,
+            _ => {},
+        }
+    }
+
+    if open.is_empty() { Some(ends) } else { None }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use optimize;
+    use program::Program;
+
+    #[test]
+    fn dead_loops() {
+        let commands = Program::compile("[]>[-.]+[-][>]>[+]", false);
+        assert_eq!(check(&commands), vec![
+            Warning::DeadLoop { start: 0, end: 1 },
+            Warning::DeadLoop { start: 3, end: 6 },
+            Warning::DeadLoop { start: 11, end: 13 },
+        ]);
+
+        // the output and final tape are the same without them
+        let raw = "[.+]>+[->+<][<<]++[.-]>.";
+        let commands = Program::compile(raw, false);
+        let removed = remove_dead_loops(commands.clone());
+        assert_eq!(removed, Program::compile(">+[->+<]++[.-]>.", false));
+        let mut before = Program::new(commands);
+        let mut after = Program::new(removed);
+        assert_eq!(before.run_to_vec(&[]).unwrap(), after.run_to_vec(&[]).unwrap());
+        assert_eq!(before.memory(), after.memory());
+    }
+
+    #[test]
+    fn empty_loops() {
+        let commands = Program::compile("+[]>,[][-]", false);
+        assert_eq!(check(&commands), vec![
+            Warning::EmptyLoop { start: 1 },
+            Warning::EmptyLoop { start: 5 },
+            Warning::DeadLoop { start: 7, end: 9 },
+        ]);
+        assert_eq!(Warning::EmptyLoop { start: 1 }.to_string(), "empty loop never ends once entered at command 1");
+
+        assert_eq!(check(&Program::compile("+[[]", false)), vec![]);
+    }
+
+    #[test]
+    fn tape_overflow() {
+        let commands = Program::compile("+>>><.>>>>", false);
+        assert_eq!(check_tape(&commands, 5), Some(Warning::TapeOverflow { at: 8 }));
+        assert_eq!(check_tape(&commands, 7), None);
+        assert_eq!(check_tape(&optimize::all(commands), 5), Some(Warning::TapeOverflow { at: 3 }));
+        assert_eq!(Warning::TapeOverflow { at: 8 }.to_string(), "pointer moves past the end of the tape at command 8");
+
+        // moves after the first loop aren't followed
+        assert_eq!(check_tape(&Program::compile("+[>>>>>>-]>>>>>>", false), 5), None);
+    }
+}
@@ -0,0 +1,230 @@
+use std::marker::PhantomData;
+
+use cell::Cell;
+use command::Command;
+use command_map::CommandMap;
+use error::BfError;
+use optimize;
+use program::{EofMode, OutputMode, Position, Program, TapeMode, DEFAULT_TAPE_SIZE};
+
+// ProgramBuilder collects the settings for a program so it can be compiled and
+// configured in one go, the cell type is picked with cells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramBuilder<C = u8> {
+    tape_size: usize,
+    start_ptr: usize,
+    eof_mode: EofMode,
+    output_mode: OutputMode,
+    tape_mode: TapeMode,
+    strict_input: bool,
+    checked_cells: bool,
+    debug: bool,
+    comment_regions: bool,
+    optimize: bool,
+    cells: PhantomData<C>,
+}
+
+impl ProgramBuilder {
+    pub fn new() -> ProgramBuilder {
+        ProgramBuilder::default()
+    }
+}
+
+impl<C: Cell> Default for ProgramBuilder<C> {
+    fn default() -> ProgramBuilder<C> {
+        ProgramBuilder {
+            tape_size: DEFAULT_TAPE_SIZE,
+            start_ptr: 0,
+            eof_mode: EofMode::Unchanged,
+            output_mode: OutputMode::Bytes,
+            tape_mode: TapeMode::Fixed,
+            strict_input: false,
+            checked_cells: false,
+            debug: false,
+            comment_regions: false,
+            optimize: false,
+            cells: PhantomData,
+        }
+    }
+}
+
+impl<C: Cell> ProgramBuilder<C> {
+    // cells switches the type of the cells on the tape, keeping the other settings
+    pub fn cells<D: Cell>(self) -> ProgramBuilder<D> {
+        ProgramBuilder {
+            tape_size: self.tape_size,
+            start_ptr: self.start_ptr,
+            eof_mode: self.eof_mode,
+            output_mode: self.output_mode,
+            tape_mode: self.tape_mode,
+            strict_input: self.strict_input,
+            checked_cells: self.checked_cells,
+            debug: self.debug,
+            comment_regions: self.comment_regions,
+            optimize: self.optimize,
+            cells: PhantomData,
+        }
+    }
+
+    // tape_size panics when the tape would have no cells
+    pub fn tape_size(mut self, tape_size: usize) -> ProgramBuilder<C> {
+        assert!(tape_size > 0, "tape must have at least one cell");
+        self.tape_size = tape_size;
+        self
+    }
+
+    // start_ptr sets the cell the pointer starts on, build fails unless it is
+    // on the tape
+    pub fn start_ptr(mut self, ptr: usize) -> ProgramBuilder<C> {
+        self.start_ptr = ptr;
+        self
+    }
+
+    pub fn eof_mode(mut self, mode: EofMode) -> ProgramBuilder<C> {
+        self.eof_mode = mode;
+        self
+    }
+
+    pub fn output_mode(mut self, mode: OutputMode) -> ProgramBuilder<C> {
+        self.output_mode = mode;
+        self
+    }
+
+    pub fn tape_mode(mut self, mode: TapeMode) -> ProgramBuilder<C> {
+        self.tape_mode = mode;
+        self
+    }
+
+    // strict_input makes reading from an empty input an error, see Program::set_strict_input
+    pub fn strict_input(mut self, strict: bool) -> ProgramBuilder<C> {
+        self.strict_input = strict;
+        self
+    }
+
+    // checked_cells makes arithmetic that would wrap a cell an error, see Program::set_checked_cells
+    pub fn checked_cells(mut self, checked: bool) -> ProgramBuilder<C> {
+        self.checked_cells = checked;
+        self
+    }
+
+    // debug makes '#' a debug command, see Program::compile
+    pub fn debug(mut self, debug: bool) -> ProgramBuilder<C> {
+        self.debug = debug;
+        self
+    }
+
+    // comment_regions skips everything between '{' and '}', see CommandMap::with_comment_region
+    pub fn comment_regions(mut self, comment_regions: bool) -> ProgramBuilder<C> {
+        self.comment_regions = comment_regions;
+        self
+    }
+
+    // optimize runs the optimizer passes in optimize::all over the program,
+    // leading loops are kept as memory may be set before it runs
+    pub fn optimize(mut self, optimize: bool) -> ProgramBuilder<C> {
+        self.optimize = optimize;
+        self
+    }
+
+    pub fn build(&self, source: &str) -> Result<Program<C>, BfError> {
+        let mut map = CommandMap::standard(self.debug);
+        if self.comment_regions {
+            map = map.with_comment_region('{', '}');
+        }
+        let (commands, positions): (Vec<Command>, Vec<Position>) = Program::tokenize_with(source, &map).into_iter().unzip();
+        let mut prog = if self.optimize {
+            Program::try_with_tape_size(optimize::all(commands), self.tape_size)?
+        } else {
+            // positions only line up with the commands when they aren't optimized
+            let mut prog = Program::try_with_tape_size(commands, self.tape_size)?;
+            prog.set_positions(positions);
+            prog
+        };
+        prog.set_start_ptr(self.start_ptr)?;
+        prog.set_eof_mode(self.eof_mode);
+        prog.set_output_mode(self.output_mode);
+        prog.set_tape_mode(self.tape_mode);
+        prog.set_strict_input(self.strict_input);
+        prog.set_checked_cells(self.checked_cells);
+        Ok(prog)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use error::BracketError;
+
+    #[test]
+    fn options_take_effect() {
+        let builder = ProgramBuilder::new()
+            .tape_size(4)
+            .eof_mode(EofMode::Zero)
+            .debug(true)
+            .optimize(true);
+
+        // echo until EOF, which only stops because EOF reads as zero
+        let mut echo = builder.build(",[.,]").unwrap();
+        assert_eq!(echo.run_to_vec(b"hi").unwrap(), b"hi");
+        assert_eq!(echo.memory().len(), 4);
+
+        // the tape is fixed at 4 cells
+        match builder.build(">>>>").unwrap().run_to_vec(&[]) {
+            Err(BfError::PointerOverflow { ptr: 4, .. }) => {},
+            other => panic!("unexpected result {:?}", other),
+        }
+        let mut grows = builder.tape_mode(TapeMode::Growable { max: None }).build(">>>>+").unwrap();
+        grows.run_to_vec(&[]).unwrap();
+        assert_eq!(grows.memory(), &[0, 0, 0, 0, 1]);
+
+        let mut wide = builder.cells::<u16>().build("-").unwrap();
+        wide.run_to_vec(&[]).unwrap();
+        assert_eq!(wide.memory()[0], u16::MAX);
+
+        let mut commented = builder.comment_regions(true).build("+{+}.").unwrap();
+        assert_eq!(commented.run_to_vec(&[]).unwrap(), vec![1]);
+
+        let mut decimal = builder.output_mode(OutputMode::Decimal).build("+.+.").unwrap();
+        assert_eq!(decimal.run_to_vec(&[]).unwrap(), b"1 2 ");
+
+        let mut started = builder.start_ptr(2).build("<+").unwrap();
+        started.run_to_vec(&[]).unwrap();
+        assert_eq!(started.memory(), &[0, 1, 0, 0]);
+
+        match builder.build("[") {
+            Err(BfError::UnbalancedBrackets(BracketError::UnmatchedOpen(0))) => {},
+            other => panic!("unexpected result {:?}", other),
+        }
+        match builder.start_ptr(10).build("+") {
+            Err(BfError::StartOffTape { ptr: 10, tape_size: 4 }) => {},
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn optimize_keeps_leading_loops() {
+        // with preloaded memory a loop at the very start can be entered
+        for &optimize in [false, true].iter() {
+            let mut prog = ProgramBuilder::new().optimize(optimize).start_ptr(1).build("[<++>-]<.").unwrap();
+            prog.set_initial_memory(vec![0, 3, 0, 0]);
+            assert_eq!(prog.run_to_vec(&[]).unwrap(), vec![6], "optimize {}", optimize);
+        }
+    }
+
+    #[test]
+    fn error_kinds() {
+        // built programs know where their commands are, errors still match on their kind
+        let mut prog = ProgramBuilder::new().tape_size(4).build("+\n<").unwrap();
+        match prog.run_to_vec(&[]) {
+            Err(BfError::PointerUnderflow { pc: 1, position: Some(position) }) => {
+                assert_eq!((position.line, position.column), (2, 1));
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+        let mut prog = ProgramBuilder::new().checked_cells(true).build(" -").unwrap();
+        match prog.run_to_vec(&[]) {
+            Err(BfError::CellOverflow { pc: 0, ptr: 0, .. }) => {},
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
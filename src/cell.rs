@@ -0,0 +1,91 @@
+use std::fmt::{Debug, Display, LowerHex};
+
+// Cell is the value type stored on the tape, arithmetic on cells always wraps
+// at the width of the type
+pub trait Cell: Copy + Default + Ord + Debug + Display + LowerHex {
+    const BITS: u32;
+    // whether the cell holds negative values
+    const SIGNED: bool;
+
+    // the value ',' stores on EOF under EofMode::AllOnes
+    fn all_ones() -> Self;
+    fn from_byte(byte: u8) -> Self;
+    // truncate the cell to the byte written by '.'
+    fn to_byte(self) -> u8;
+    fn wrapping_inc(self) -> Self;
+    fn wrapping_dec(self) -> Self;
+    // add a possibly negative amount, wrapping at the width of the cell
+    fn add_delta(self, delta: i32) -> Self;
+    // add value times factor, wrapping at the width of the cell
+    fn add_product(self, value: Self, factor: i32) -> Self;
+    // the same as add_delta and add_product but None when the result doesn't
+    // fit in the cell rather than wrapping
+    fn checked_add_delta(self, delta: i32) -> Option<Self>;
+    fn checked_add_product(self, value: Self, factor: i32) -> Option<Self>;
+
+    fn is_zero(self) -> bool {
+        self == Self::default()
+    }
+}
+
+macro_rules! impl_cell {
+    ($t:ty, $bits:expr, $signed:expr) => {
+        impl Cell for $t {
+            const BITS: u32 = $bits;
+            const SIGNED: bool = $signed;
+
+            fn all_ones() -> $t {
+                !0
+            }
+
+            fn from_byte(byte: u8) -> $t {
+                byte as $t
+            }
+
+            fn to_byte(self) -> u8 {
+                self as u8
+            }
+
+            fn wrapping_inc(self) -> $t {
+                self.wrapping_add(1)
+            }
+
+            fn wrapping_dec(self) -> $t {
+                self.wrapping_sub(1)
+            }
+
+            fn add_delta(self, delta: i32) -> $t {
+                // cells are at most 32 bits wide so truncating the delta keeps
+                // it correct modulo the width of the cell
+                self.wrapping_add(delta as $t)
+            }
+
+            fn add_product(self, value: $t, factor: i32) -> $t {
+                self.wrapping_add(value.wrapping_mul(factor as $t))
+            }
+
+            fn checked_add_delta(self, delta: i32) -> Option<$t> {
+                self.checked_add_product(1, delta)
+            }
+
+            fn checked_add_product(self, value: $t, factor: i32) -> Option<$t> {
+                // every cell and factor fits in an i128 with room for the product
+                let sum = self as i128 + value as i128 * factor as i128;
+                if sum < <$t>::MIN as i128 || sum > <$t>::MAX as i128 {
+                    None
+                } else {
+                    Some(sum as $t)
+                }
+            }
+        }
+    };
+}
+
+impl_cell!(u8, 8, false);
+impl_cell!(u16, 16, false);
+impl_cell!(u32, 32, false);
+// signed cells behave the same under two's complement, they only differ in
+// how a cell reads when inspected
+impl_cell!(i8, 8, true);
+impl_cell!(i16, 16, true);
+impl_cell!(i32, 32, true);
@@ -0,0 +1,66 @@
+use std::io::{self, Read};
+use std::sync::mpsc::Receiver;
+
+// ChannelInput reads the bytes sent down a channel so input can be given to a
+// program while it runs, a read waits until a byte arrives and the input ends
+// once every sender has been dropped
+#[derive(Debug)]
+pub struct ChannelInput {
+    receiver: Receiver<u8>,
+}
+
+impl ChannelInput {
+    pub fn new(receiver: Receiver<u8>) -> ChannelInput {
+        ChannelInput { receiver }
+    }
+}
+
+impl Read for ChannelInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        buf[0] = match self.receiver.recv() {
+            Ok(byte) => byte,
+            Err(_) => return Ok(0),
+        };
+
+        // fill the rest with whatever has already arrived without waiting
+        let mut n = 1;
+        while n < buf.len() {
+            match self.receiver.try_recv() {
+                Ok(byte) => buf[n] = byte,
+                Err(_) => break,
+            }
+            n += 1;
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use program::{EofMode, Program};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn streamed_input() {
+        let (sender, receiver) = mpsc::channel();
+        let feeder = thread::spawn(move || {
+            for &byte in b"hello" {
+                thread::sleep(Duration::from_millis(5));
+                sender.send(byte).unwrap();
+            }
+        });
+
+        let mut prog = Program::from_str(",[.,]");
+        prog.set_eof_mode(EofMode::Zero);
+        let mut output = Vec::new();
+        prog.run(&mut ChannelInput::new(receiver), &mut output).unwrap();
+        feeder.join().unwrap();
+        assert_eq!(output, b"hello");
+    }
+}
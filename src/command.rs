@@ -0,0 +1,132 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Command {
+    Right,
+    Left,
+    Inc,
+    Dec,
+    Out,
+    In,
+    // the jump targets are the pc of the matching bracket, compile leaves them
+    // as 0 and they are filled in when a Program is created
+    JmpFwd(usize),
+    JmpBack(usize),
+    Debug,
+    // produced by the optimizer, see the passes in optimize
+    Add(i32),
+    Move(isize),
+    Clear,
+    // add the current cell times each factor to the cell at each offset,
+    // then clear the current cell
+    MulAdd(Vec<(isize, i32)>),
+}
+
+impl Command {
+    // kind is the name of the command without any operands
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Command::Right => "Right",
+            Command::Left => "Left",
+            Command::Inc => "Inc",
+            Command::Dec => "Dec",
+            Command::Out => "Out",
+            Command::In => "In",
+            Command::JmpFwd(_) => "JmpFwd",
+            Command::JmpBack(_) => "JmpBack",
+            Command::Debug => "Debug",
+            Command::Add(_) => "Add",
+            Command::Move(_) => "Move",
+            Command::Clear => "Clear",
+            Command::MulAdd(_) => "MulAdd",
+        }
+    }
+
+    // describe explains in plain English what the command does, brackets
+    // name the index of the bracket they jump to
+    pub fn describe(&self) -> String {
+        match *self {
+            Command::Right => String::from("move the pointer right"),
+            Command::Left => String::from("move the pointer left"),
+            Command::Inc => String::from("increment the current cell"),
+            Command::Dec => String::from("decrement the current cell"),
+            Command::Out => String::from("output the current cell"),
+            Command::In => String::from("read a byte of input into the current cell"),
+            Command::JmpFwd(target) => format!("jump past the matching ] at {} if the current cell is 0", target),
+            Command::JmpBack(target) => format!("jump back to the matching [ at {} unless the current cell is 0", target),
+            Command::Debug => String::from("print debug information"),
+            Command::Add(n) if n < 0 => format!("subtract {} from the current cell", n.unsigned_abs()),
+            Command::Add(n) => format!("add {} to the current cell", n),
+            Command::Move(n) if n < 0 => format!("move the pointer {} cells left", n.unsigned_abs()),
+            Command::Move(n) => format!("move the pointer {} cells right", n),
+            Command::Clear => String::from("set the current cell to 0"),
+            Command::MulAdd(ref terms) => {
+                let adds: Vec<String> = terms.iter()
+                    .map(|&(offset, factor)| format!("add {} times the current cell to the cell {} away", factor, offset))
+                    .collect();
+                format!("{}, then set the current cell to 0", adds.join(", "))
+            },
+        }
+    }
+}
+
+// a command is displayed as the brainfuck source it stands for, the commands
+// made by the optimizer are spelled out as the loops they replace
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Command::Right => f.write_str(">"),
+            Command::Left => f.write_str("<"),
+            Command::Inc => f.write_str("+"),
+            Command::Dec => f.write_str("-"),
+            Command::Out => f.write_str("."),
+            Command::In => f.write_str(","),
+            Command::JmpFwd(_) => f.write_str("["),
+            Command::JmpBack(_) => f.write_str("]"),
+            Command::Debug => f.write_str("#"),
+            Command::Add(n) => write_repeated(f, if n < 0 { '-' } else { '+' }, n.unsigned_abs() as usize),
+            Command::Move(n) => write_repeated(f, if n < 0 { '<' } else { '>' }, n.unsigned_abs()),
+            Command::Clear => f.write_str("[-]"),
+            Command::MulAdd(ref terms) => {
+                f.write_str("[-")?;
+                let mut offset = 0;
+                for &(target, factor) in terms {
+                    Command::Move(target - offset).fmt(f)?;
+                    Command::Add(factor).fmt(f)?;
+                    offset = target;
+                }
+                Command::Move(-offset).fmt(f)?;
+                f.write_str("]")
+            },
+        }
+    }
+}
+
+fn write_repeated(f: &mut fmt::Formatter, c: char, n: usize) -> fmt::Result {
+    for _ in 0..n {
+        write!(f, "{}", c)?;
+    }
+    Ok(())
+}
+
+// the command a source byte stands for, anything else is a comment
+pub(crate) fn command_for(b: u8, debug_pound: bool) -> Option<Command> {
+    match b {
+        b'>' => Some(Command::Right),
+        b'<' => Some(Command::Left),
+        b'+' => Some(Command::Inc),
+        b'-' => Some(Command::Dec),
+        b'.' => Some(Command::Out),
+        b',' => Some(Command::In),
+        b'[' => Some(Command::JmpFwd(0)),
+        b']' => Some(Command::JmpBack(0)),
+        b'#' if debug_pound => Some(Command::Debug),
+        _ => None,
+    }
+}
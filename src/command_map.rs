@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+
+use command::Command;
+
+// CommandMap says which command each character of a program stands for, the
+// default is standard brainfuck and anything not in the map is a comment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandMap {
+    commands: HashMap<char, Command>,
+    // characters that open and close a region that is skipped entirely
+    comment_region: Option<(char, char)>,
+}
+
+impl CommandMap {
+    // empty maps no characters at all, commands are added with insert or with
+    pub fn empty() -> CommandMap {
+        CommandMap { commands: HashMap::new(), comment_region: None }
+    }
+
+    // standard is the usual brainfuck characters, with '#' as the debug
+    // command if debug_pound is true
+    pub fn standard(debug_pound: bool) -> CommandMap {
+        let mut map = CommandMap::empty()
+            .with('>', Command::Right)
+            .with('<', Command::Left)
+            .with('+', Command::Inc)
+            .with('-', Command::Dec)
+            .with('.', Command::Out)
+            .with(',', Command::In)
+            .with('[', Command::JmpFwd(0))
+            .with(']', Command::JmpBack(0));
+        if debug_pound {
+            map.insert('#', Command::Debug);
+        }
+        map
+    }
+
+    // with is insert for building a map in one expression
+    pub fn with(mut self, c: char, command: Command) -> CommandMap {
+        self.insert(c, command);
+        self
+    }
+
+    // with_comment_region skips everything from open to the matching close,
+    // commands included, regions may be nested
+    pub fn with_comment_region(mut self, open: char, close: char) -> CommandMap {
+        self.comment_region = Some((open, close));
+        self
+    }
+
+    pub fn comment_region(&self) -> Option<(char, char)> {
+        self.comment_region
+    }
+
+    // insert makes c stand for command, replacing whatever it stood for before
+    pub fn insert(&mut self, c: char, command: Command) {
+        self.commands.insert(c, command);
+    }
+
+    // remove turns c back into a comment
+    pub fn remove(&mut self, c: char) {
+        self.commands.remove(&c);
+    }
+
+    pub fn get(&self, c: char) -> Option<&Command> {
+        self.commands.get(&c)
+    }
+}
+
+impl Default for CommandMap {
+    fn default() -> CommandMap {
+        CommandMap::standard(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use program::Program;
+
+    #[test]
+    fn remapped() {
+        let standard = "++[>+<-]>.,#";
+        let map = CommandMap::empty()
+            .with('r', Command::Right)
+            .with('l', Command::Left)
+            .with('i', Command::Inc)
+            .with('d', Command::Dec)
+            .with('o', Command::Out)
+            .with('n', Command::In)
+            .with('(', Command::JmpFwd(0))
+            .with(')', Command::JmpBack(0))
+            .with('?', Command::Debug);
+        let remapped = "ii(rild)ron? +-";
+        assert_eq!(Program::compile_with(remapped, &map), Program::compile(standard, true));
+
+        // characters from the standard map are comments unless they are mapped
+        let mut map = CommandMap::default();
+        assert_eq!(map.get('#'), None);
+        map.remove('.');
+        map.insert('!', Command::Out);
+        assert_eq!(Program::compile_with("+.!", &map), vec![Command::Inc, Command::Out]);
+    }
+
+    #[test]
+    fn comment_regions() {
+        let map = CommandMap::default().with_comment_region('{', '}');
+        let raw = "+{ not run: [-]>. {nested -} still not run: , }>}+";
+        assert_eq!(Program::compile_with(raw, &map), vec![Command::Inc, Command::Right, Command::Inc]);
+        assert_eq!(Program::compile_with(raw, &CommandMap::default()).len(), 10);
+
+        // an unclosed region runs to the end of the program
+        assert_eq!(Program::compile_with("+{-", &map), vec![Command::Inc]);
+    }
+}
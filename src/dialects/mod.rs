@@ -0,0 +1,4 @@
+// Dialects are languages that are brainfuck under a different spelling, each
+// one translates its source into commands that Program::new runs as usual.
+
+pub mod ook;
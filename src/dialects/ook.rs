@@ -0,0 +1,69 @@
+// Ook! spells each brainfuck command as a pair of the words "Ook.", "Ook?" and
+// "Ook!", anything that isn't one of those words is a comment.
+
+use command::Command;
+
+// compile translates Ook! source into commands, a pair that doesn't stand for
+// a command and an unpaired word at the end are ignored
+pub fn compile(source: &str) -> Vec<Command> {
+    let mut marks = Vec::new();
+    let mut rest = source;
+    while let Some(i) = rest.find("Ook") {
+        rest = &rest[i + 3..];
+        if let Some(&mark @ (b'.' | b'?' | b'!')) = rest.as_bytes().first() {
+            marks.push(mark);
+            rest = &rest[1..];
+        }
+    }
+
+    marks.chunks(2).filter_map(|pair| match *pair {
+        [b'.', b'?'] => Some(Command::Right),
+        [b'?', b'.'] => Some(Command::Left),
+        [b'.', b'.'] => Some(Command::Inc),
+        [b'!', b'!'] => Some(Command::Dec),
+        [b'!', b'.'] => Some(Command::Out),
+        [b'.', b'!'] => Some(Command::In),
+        [b'!', b'?'] => Some(Command::JmpFwd(0)),
+        [b'?', b'!'] => Some(Command::JmpBack(0)),
+        _ => None,
+    }).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use program::Program;
+
+    const HELLO_WORLD: &str = "
+Ook. Ook. Ook. Ook. Ook. Ook. Ook. Ook. Ook. Ook. Ook. Ook.
+Ook. Ook. Ook. Ook. Ook! Ook? Ook. Ook? Ook. Ook. Ook. Ook.
+Ook. Ook. Ook. Ook. Ook! Ook? Ook. Ook? Ook. Ook. Ook. Ook.
+Ook. Ook? Ook. Ook. Ook. Ook. Ook. Ook. Ook. Ook? Ook. Ook.
+Ook. Ook. Ook. Ook. Ook. Ook? Ook. Ook. Ook? Ook. Ook? Ook.
+Ook? Ook. Ook? Ook. Ook! Ook! Ook? Ook! Ook. Ook? Ook. Ook.
+Ook. Ook? Ook. Ook. Ook. Ook? Ook! Ook! Ook. Ook? Ook. Ook?
+Ook. Ook. Ook! Ook? Ook? Ook. Ook? Ook! Ook? Ook. Ook! Ook!
+Ook? Ook! Ook. Ook? Ook. Ook? Ook! Ook. Ook. Ook? Ook! Ook!
+Ook! Ook! Ook! Ook! Ook! Ook. Ook. Ook. Ook. Ook. Ook. Ook.
+Ook. Ook. Ook. Ook. Ook. Ook. Ook. Ook. Ook! Ook. Ook! Ook.
+Ook. Ook. Ook. Ook. Ook. Ook. Ook! Ook. Ook. Ook? Ook. Ook?
+Ook! Ook. Ook? Ook. Ook! Ook! Ook! Ook. Ook? Ook. Ook! Ook.
+Ook. Ook. Ook. Ook. Ook. Ook. Ook! Ook. Ook! Ook! Ook! Ook!
+Ook! Ook! Ook! Ook! Ook! Ook! Ook! Ook! Ook! Ook. Ook! Ook!
+Ook! Ook! Ook! Ook! Ook! Ook! Ook! Ook! Ook! Ook! Ook! Ook!
+Ook! Ook! Ook! Ook. Ook. Ook? Ook. Ook? Ook. Ook. Ook! Ook.
+Ook. Ook? Ook. Ook. Ook. Ook. Ook! Ook.
+";
+
+    #[test]
+    fn hello_world() {
+        let mut prog = Program::new(compile(HELLO_WORLD));
+        assert_eq!(prog.run_to_vec(&[]).unwrap(), b"Hello World!\n");
+    }
+
+    #[test]
+    fn comments() {
+        let commands = compile("Ook. Ook? moves right, Ook? Ook? does nothing and Ook! Ook.Ook. Ook");
+        assert_eq!(commands, vec![Command::Right, Command::Out]);
+    }
+}
@@ -0,0 +1,167 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub use machine::BracketError;
+use program::Position;
+
+impl Error for BracketError {}
+
+// UnmatchedBracket is a BracketError traced back to where the bracket is in
+// the source the program was compiled from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnmatchedBracket {
+    pub error: BracketError,
+    pub position: Position,
+}
+
+impl fmt::Display for UnmatchedBracket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bracket = match self.error {
+            BracketError::UnmatchedOpen(_) => '[',
+            BracketError::UnmatchedClose(_) => ']',
+        };
+        write!(f, "unmatched '{}' at byte {} (line {}, column {})", bracket, self.position.offset, self.position.line, self.position.column)
+    }
+}
+
+impl Error for UnmatchedBracket {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+// ParseError is a character that isn't a command found while tokenizing strictly
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseError {
+    pub found: char,
+    pub position: Position,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unexpected {:?} at line {}, column {}", self.found, self.position.line, self.position.column)
+    }
+}
+
+impl Error for ParseError {}
+
+// Diagnostic is a problem found in program source by validate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub position: Position,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at line {}, column {}", self.message, self.position.line, self.position.column)
+    }
+}
+
+// IncludeError stops include directives from being expanded
+#[derive(Debug)]
+pub enum IncludeError {
+    Io { path: PathBuf, error: io::Error },
+    // the file at path is already being included further up
+    Cycle(PathBuf),
+}
+
+impl fmt::Display for IncludeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IncludeError::Io { ref path, ref error } => write!(f, "could not read {}: {}", path.display(), error),
+            IncludeError::Cycle(ref path) => write!(f, "{} includes itself", path.display()),
+        }
+    }
+}
+
+impl Error for IncludeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            IncludeError::Io { ref error, .. } => Some(error),
+            IncludeError::Cycle(_) => None,
+        }
+    }
+}
+
+// BfError is anything that can stop a program from being built or run, the
+// errors a command causes carry its position in the source when that is known
+#[derive(Debug)]
+pub enum BfError {
+    Io(io::Error),
+    PointerUnderflow { pc: usize, position: Option<Position> },
+    PointerOverflow { pc: usize, ptr: usize, position: Option<Position> },
+    StepLimitExceeded { pc: usize, steps: u64 },
+    Timeout { pc: usize, timeout: Duration },
+    OutputLimitExceeded { pc: usize, limit: u64 },
+    // the flag given to run_cancellable was set
+    Cancelled { pc: usize },
+    // a cell would have wrapped with checked cells turned on
+    CellOverflow { pc: usize, ptr: usize, position: Option<Position> },
+    // ',' was executed in strict input mode without any input given
+    NoInput { pc: usize, position: Option<Position> },
+    UnbalancedBrackets(BracketError),
+    // the pointer was set to start on a cell past the end of the tape
+    StartOffTape { ptr: usize, tape_size: usize },
+    // a program was given a tape without any cells
+    EmptyTape,
+}
+
+impl BfError {
+    // position is where in the source the command that caused the error was
+    pub fn position(&self) -> Option<Position> {
+        match *self {
+            BfError::PointerUnderflow { position, .. } | BfError::PointerOverflow { position, .. }
+                | BfError::CellOverflow { position, .. } | BfError::NoInput { position, .. } => position,
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BfError::Io(ref e) => write!(f, "{}", e),
+            BfError::PointerUnderflow { pc, .. } => write!(f, "tape underflow at pc {}", pc),
+            BfError::PointerOverflow { pc, ptr, .. } => write!(f, "tape overflow at pc {}: pointer {}", pc, ptr),
+            BfError::StepLimitExceeded { pc, steps } => write!(f, "step limit of {} exceeded at pc {}", steps, pc),
+            BfError::Timeout { pc, timeout } => write!(f, "timed out after {}ms at pc {}", timeout.as_millis(), pc),
+            BfError::OutputLimitExceeded { pc, limit } => write!(f, "output limit of {} exceeded at pc {}", limit, pc),
+            BfError::Cancelled { pc } => write!(f, "cancelled at pc {}", pc),
+            BfError::CellOverflow { pc, ptr, .. } => write!(f, "cell {} overflowed at pc {}", ptr, pc),
+            BfError::NoInput { pc, .. } => write!(f, "input read at pc {} but none was given", pc),
+            BfError::UnbalancedBrackets(ref e) => write!(f, "{}", e),
+            BfError::StartOffTape { ptr, tape_size } => write!(f, "start pointer {} is off the tape of {} cells", ptr, tape_size),
+            BfError::EmptyTape => write!(f, "tape must have at least one cell"),
+        }?;
+        match self.position() {
+            Some(position) => write!(f, " (line {}, column {})", position.line, position.column),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Error for BfError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            BfError::Io(ref e) => Some(e),
+            BfError::UnbalancedBrackets(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BfError {
+    fn from(e: io::Error) -> BfError {
+        BfError::Io(e)
+    }
+}
+
+impl From<BracketError> for BfError {
+    fn from(e: BracketError) -> BfError {
+        BfError::UnbalancedBrackets(e)
+    }
+}
@@ -0,0 +1,94 @@
+// Includes let a program be split over several files. A line holding only
+// "@include PATH" is replaced by the contents of that file, which may include
+// others in turn. Paths are relative to the directory of the file the
+// directive is in. Nothing here runs unless asked for, '@' is otherwise just
+// a comment like any other character.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use error::IncludeError;
+
+const DIRECTIVE: &str = "@include ";
+
+// expand replaces the include directives in source, paths are looked up
+// relative to dir
+pub fn expand(source: &str, dir: &Path) -> Result<String, IncludeError> {
+    expand_in(source, dir, &mut Vec::new())
+}
+
+// expand_in does the work of expand, stack holds the files being expanded so
+// a file that ends up including itself is caught
+fn expand_in(source: &str, dir: &Path, stack: &mut Vec<PathBuf>) -> Result<String, IncludeError> {
+    let mut out = String::with_capacity(source.len());
+    for line in source.split_inclusive('\n') {
+        let path = match line.trim().strip_prefix(DIRECTIVE) {
+            Some(path) => dir.join(path.trim()),
+            None => {
+                out.push_str(line);
+                continue;
+            },
+        };
+
+        let canonical = fs::canonicalize(&path).map_err(|error| IncludeError::Io { path: path.clone(), error })?;
+        if stack.contains(&canonical) {
+            return Err(IncludeError::Cycle(path));
+        }
+        let included = fs::read_to_string(&path).map_err(|error| IncludeError::Io { path: path.clone(), error })?;
+
+        stack.push(canonical);
+        let parent = path.parent().unwrap_or(dir).to_path_buf();
+        out.push_str(&expand_in(&included, &parent, stack)?);
+        stack.pop();
+        // keep the line break so the next line doesn't join the included source
+        if line.ends_with('\n') && !out.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use program::Program;
+    use std::env;
+    use std::process;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("brainfuck-include-{}-{}", name, process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn include() {
+        let dir = temp_dir("nested");
+        // set cell 1 to 65 and print it
+        fs::write(dir.join("lib/print.bf"), "@include times.bf\n>.<\n").unwrap();
+        fs::write(dir.join("lib/times.bf"), "++++++++[>++++++++<-]>+<").unwrap();
+        let source = expand("a program.\n  @include lib/print.bf\n+++.", &dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let source = source.unwrap();
+        assert_eq!(source, "a program.\n++++++++[>++++++++<-]>+<\n>.<\n+++.");
+        assert_eq!(Program::from_str(&source).run_to_vec(&[]).unwrap(), vec![0, 65, 3]);
+    }
+
+    #[test]
+    fn include_errors() {
+        let dir = temp_dir("cycle");
+        fs::write(dir.join("a.bf"), "+\n@include lib/b.bf\n").unwrap();
+        fs::write(dir.join("lib/b.bf"), "@include ../a.bf\n").unwrap();
+        let cycle = expand("@include a.bf", &dir).unwrap_err();
+        let missing = expand("@include nothing.bf", &dir).unwrap_err();
+        fs::remove_dir_all(&dir).unwrap();
+
+        match cycle {
+            IncludeError::Cycle(path) => assert!(path.ends_with("lib/../a.bf"), "{:?}", path),
+            e => panic!("unexpected error {:?}", e),
+        }
+        assert!(missing.to_string().starts_with("could not read "), "{}", missing);
+    }
+}
@@ -0,0 +1,163 @@
+// without the std feature only the core interpreter in machine is built, it
+// needs nothing but an allocator
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg_attr(not(any(feature = "std", test)), macro_use)]
+extern crate alloc;
+#[cfg(not(any(feature = "std", test)))]
+extern crate core as std;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+
+#[cfg(feature = "std")]
+pub mod analyze;
+#[cfg(feature = "std")]
+mod builder;
+mod cell;
+#[cfg(feature = "std")]
+mod channel;
+mod command;
+#[cfg(feature = "std")]
+mod command_map;
+#[cfg(feature = "std")]
+pub mod dialects;
+#[cfg(feature = "std")]
+mod error;
+#[cfg(feature = "std")]
+pub mod include;
+#[cfg(feature = "std")]
+mod line_input;
+pub mod machine;
+#[cfg(feature = "std")]
+pub mod optimize;
+#[cfg(feature = "std")]
+mod program;
+#[cfg(feature = "std")]
+mod random;
+#[cfg(feature = "std")]
+pub mod transpile;
+
+#[cfg(feature = "std")]
+pub use builder::ProgramBuilder;
+pub use cell::Cell;
+#[cfg(feature = "std")]
+pub use channel::ChannelInput;
+pub use command::Command;
+#[cfg(feature = "std")]
+pub use command_map::CommandMap;
+#[cfg(feature = "std")]
+pub use error::{BfError, BracketError, Diagnostic, IncludeError, ParseError, UnmatchedBracket};
+#[cfg(feature = "std")]
+pub use line_input::LineInput;
+#[cfg(feature = "std")]
+pub use optimize::OpIR;
+#[cfg(feature = "std")]
+pub use program::{CostModel, EofMode, Limits, LoopStats, MachineState, Observers, OutputMode, Position, Program, RunStats, StepResult, TapeMode, DEFAULT_TAPE_SIZE};
+#[cfg(feature = "std")]
+pub use random::RandomInput;
+
+// execute compiles and runs source with the default settings, returning
+// everything the program wrote
+#[cfg(feature = "std")]
+pub fn execute(source: &str, input: &[u8]) -> Result<Vec<u8>, BfError> {
+    let mut prog: Program = ProgramBuilder::new().build(source)?;
+    prog.run_to_vec(input)
+}
+
+// validate checks source for unmatched brackets and, when strict, for any
+// character that isn't a command or whitespace, without running it. Every
+// problem is returned in the order it appears in the source.
+#[cfg(feature = "std")]
+pub fn validate(source: &str, strict: bool) -> Result<(), Vec<Diagnostic>> {
+    validate_with(source, &CommandMap::standard(false), strict)
+}
+
+// validate_with is validate for a program written with the characters in map
+#[cfg(feature = "std")]
+pub fn validate_with(source: &str, map: &CommandMap, strict: bool) -> Result<(), Vec<Diagnostic>> {
+    let (tokens, strays) = Program::scan(source, map);
+    let mut diagnostics = Vec::new();
+    if strict {
+        diagnostics.extend(strays.into_iter().map(|stray| Diagnostic {
+            position: stray.position,
+            message: format!("unexpected {:?}", stray.found),
+        }));
+    }
+
+    check_brackets(&tokens, diagnostics)
+}
+
+// validate_tokens is validate for a program that has already been tokenized,
+// as by Program::tokenize_reader, only its brackets can be checked
+#[cfg(feature = "std")]
+pub fn validate_tokens(tokens: &[(Command, Position)]) -> Result<(), Vec<Diagnostic>> {
+    check_brackets(tokens, Vec::new())
+}
+
+// check_brackets adds the unmatched brackets in tokens to diagnostics and
+// gives them all in source order
+#[cfg(feature = "std")]
+fn check_brackets(tokens: &[(Command, Position)], mut diagnostics: Vec<Diagnostic>) -> Result<(), Vec<Diagnostic>> {
+    let mut open = Vec::new();
+    for &(ref command, position) in tokens {
+        match *command {
+            Command::JmpFwd(_) => open.push(position),
+            Command::JmpBack(_) if open.pop().is_none() => {
+                diagnostics.push(Diagnostic { position, message: String::from("unmatched ']'") });
+            },
+            _ => {},
+        }
+    }
+    diagnostics.extend(open.into_iter().map(|position| Diagnostic { position, message: String::from("unmatched '['") }));
+
+    if diagnostics.is_empty() {
+        return Ok(());
+    }
+    diagnostics.sort_by_key(|diagnostic| diagnostic.position.offset);
+    Err(diagnostics)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn execute_hello_world() {
+        let hello = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+        assert_eq!(execute(hello, &[]).unwrap(), b"Hello World!\n");
+        assert_eq!(execute(",+.", b"a").unwrap(), b"b");
+        assert!(execute("[", &[]).is_err());
+    }
+
+    #[test]
+    fn validate_all() {
+        assert_eq!(validate("+[>.<-] ok", false), Ok(()));
+
+        let messages = |source, strict| -> Vec<String> {
+            validate(source, strict).unwrap_err().iter().map(|d| d.to_string()).collect()
+        };
+        assert_eq!(messages("][+\n[[-]", false), vec![
+            "unmatched ']' at line 1, column 1",
+            "unmatched '[' at line 1, column 2",
+            "unmatched '[' at line 2, column 1",
+        ]);
+        assert_eq!(messages("+x[\n y", true), vec![
+            "unexpected 'x' at line 1, column 2",
+            "unmatched '[' at line 1, column 3",
+            "unexpected 'y' at line 2, column 2",
+        ]);
+        assert_eq!(validate("+x", false), Ok(()));
+
+        // brackets are whatever the map says they are
+        let map = CommandMap::standard(false).with_comment_region('{', '}');
+        assert_eq!(validate_with("{[}+", &map, true), Ok(()));
+        let map = CommandMap::empty().with('(', Command::JmpFwd(0));
+        let messages: Vec<String> = validate_with("(]", &map, true).unwrap_err().iter().map(|d| d.to_string()).collect();
+        assert_eq!(messages, vec!["unmatched '[' at line 1, column 1", "unexpected ']' at line 1, column 2"]);
+        // tokens read without holding the source have their brackets checked the same way
+        let tokens = Program::tokenize_reader(&b"][+\n[[-]"[..], false).unwrap();
+        assert_eq!(validate_tokens(&tokens), validate("][+\n[[-]", false));
+    }
+}
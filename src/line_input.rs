@@ -0,0 +1,71 @@
+use std::io::{self, BufRead, Read};
+
+// LineInput hands out input a line at a time, the next line is only read once
+// every byte of the last one has been used so an interactive program waits for
+// a line when it needs one and never before
+#[derive(Debug)]
+pub struct LineInput<R> {
+    reader: R,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: BufRead> LineInput<R> {
+    pub fn new(reader: R) -> LineInput<R> {
+        LineInput { reader, line: Vec::new(), pos: 0 }
+    }
+}
+
+impl<R: BufRead> Read for LineInput<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.line.len() {
+            self.line.clear();
+            self.pos = 0;
+            self.reader.read_until(b'\n', &mut self.line)?;
+        }
+        let n = usize::min(buf.len(), self.line.len() - self.pos);
+        buf[..n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use program::{EofMode, Program};
+    use std::io::Cursor;
+
+    #[test]
+    fn one_line_at_a_time() {
+        let mut source = Cursor::new(b"ab\ncd\n\nef".to_vec());
+        {
+            let mut input = LineInput::new(&mut source);
+            let mut buf = [0; 8];
+            assert_eq!(1, input.read(&mut buf[..1]).unwrap());
+            assert_eq!(b'a', buf[0]);
+        }
+        // only the first line was taken from the source
+        assert_eq!(3, source.position());
+
+        let mut input = LineInput::new(Cursor::new(b"ab\ncd\n\nef".to_vec()));
+        let mut buf = [0; 8];
+        let mut lines = Vec::new();
+        loop {
+            match input.read(&mut buf).unwrap() {
+                0 => break,
+                n => lines.push(buf[..n].to_vec()),
+            }
+        }
+        assert_eq!(lines, vec![b"ab\n".to_vec(), b"cd\n".to_vec(), b"\n".to_vec(), b"ef".to_vec()]);
+    }
+
+    #[test]
+    fn multi_line_program() {
+        let mut prog = Program::from_str(",[.,]");
+        prog.set_eof_mode(EofMode::Zero);
+        let mut output = Vec::new();
+        prog.run(&mut LineInput::new(Cursor::new(b"first\n\nthird\n".to_vec())), &mut output).unwrap();
+        assert_eq!(output, b"first\n\nthird\n");
+    }
+}
@@ -0,0 +1,295 @@
+// machine is the core of the interpreter, it only needs an allocator so it can
+// be used without std. step executes a command against anything that is a
+// Machine, Program runs its commands through it. run executes a whole program
+// on a slice, reading and writing through ByteInput and ByteOutput rather than
+// io::Read and io::Write, and has none of the settings of Program: end of
+// input leaves the cell unchanged and the tape never grows.
+
+use alloc::vec::Vec;
+use std::fmt;
+
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
+
+use cell::Cell;
+use command::{command_for, Command};
+#[cfg(feature = "std")]
+use program::read_byte;
+
+// ByteInput is where ',' reads from, None is the end of input
+pub trait ByteInput {
+    type Error;
+    fn read_byte(&mut self) -> Result<Option<u8>, Self::Error>;
+}
+
+// ByteOutput is where '.' writes to
+pub trait ByteOutput {
+    type Error;
+    fn write_byte(&mut self, byte: u8) -> Result<(), Self::Error>;
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> ByteInput for R {
+    type Error = io::Error;
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        read_byte(self)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> ByteOutput for W {
+    type Error = io::Error;
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.write_all(&[byte])
+    }
+}
+
+// Fault is why run stopped before the end of the program, E is the error of
+// the input and output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault<E> {
+    UnmatchedOpen(usize),
+    UnmatchedClose(usize),
+    PointerUnderflow { pc: usize },
+    PointerOverflow { pc: usize, ptr: usize },
+    Io(E),
+}
+
+// BracketError points at the first bracket that has no partner,
+// given as an index into the compiled commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BracketError {
+    UnmatchedOpen(usize),
+    UnmatchedClose(usize),
+}
+
+impl BracketError {
+    // index is where in the commands the bracket is
+    pub fn index(&self) -> usize {
+        match *self {
+            BracketError::UnmatchedOpen(i) | BracketError::UnmatchedClose(i) => i,
+        }
+    }
+}
+
+impl fmt::Display for BracketError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BracketError::UnmatchedOpen(i) => write!(f, "unmatched '[' at command {}", i),
+            BracketError::UnmatchedClose(i) => write!(f, "unmatched ']' at command {}", i),
+        }
+    }
+}
+
+impl<E> From<BracketError> for Fault<E> {
+    fn from(e: BracketError) -> Fault<E> {
+        match e {
+            BracketError::UnmatchedOpen(i) => Fault::UnmatchedOpen(i),
+            BracketError::UnmatchedClose(i) => Fault::UnmatchedClose(i),
+        }
+    }
+}
+
+// compile turns source into commands, if debug_pound is true then '#' is
+// compiled as a debug command, which run skips
+pub fn compile(source: &[u8], debug_pound: bool) -> Vec<Command> {
+    source.iter().filter_map(|&b| command_for(b, debug_pound)).collect()
+}
+
+// link points each bracket in commands at its match
+pub fn link(commands: &mut [Command]) -> Result<(), BracketError> {
+    let mut open = Vec::new();
+    for pc in 0..commands.len() {
+        match commands[pc] {
+            Command::JmpFwd(_) => open.push(pc),
+            Command::JmpBack(_) => {
+                let start = open.pop().ok_or(BracketError::UnmatchedClose(pc))?;
+                commands[start] = Command::JmpFwd(pc);
+                commands[pc] = Command::JmpBack(start);
+            },
+            _ => {},
+        }
+    }
+    match open.first() {
+        Some(&start) => Err(BracketError::UnmatchedOpen(start)),
+        None => Ok(()),
+    }
+}
+
+// Machine is the tape and the input and output step runs a command against,
+// so the same steps run on a plain slice here and on the tape of a Program.
+// Every method is given the pc of the command for its errors.
+pub trait Machine {
+    type Cell: Cell;
+    type Error;
+
+    // current is the cell under the pointer
+    fn current(&mut self) -> &mut Self::Cell;
+    fn move_by(&mut self, delta: isize, pc: usize) -> Result<(), Self::Error>;
+    // add_product adds value times factor to the cell offset from the pointer,
+    // leaving the pointer where it is
+    fn add_product(&mut self, offset: isize, value: Self::Cell, factor: i32, pc: usize) -> Result<(), Self::Error>;
+    // output writes the current cell
+    fn output(&mut self, pc: usize) -> Result<(), Self::Error>;
+    // input reads into the current cell
+    fn input(&mut self, pc: usize) -> Result<(), Self::Error>;
+
+    // add adds delta to the current cell, wrapping at its width
+    fn add(&mut self, delta: i32, _pc: usize) -> Result<(), Self::Error> {
+        let cell = self.current();
+        *cell = cell.add_delta(delta);
+        Ok(())
+    }
+}
+
+// step executes the command at pc and gives the pc of the command to run next,
+// the brackets must have been linked. Debug commands are skipped.
+pub fn step<M: Machine>(machine: &mut M, commands: &[Command], pc: usize) -> Result<usize, M::Error> {
+    match commands[pc] {
+        Command::Right => machine.move_by(1, pc)?,
+        Command::Left => machine.move_by(-1, pc)?,
+        Command::Move(delta) => machine.move_by(delta, pc)?,
+        Command::Inc => machine.add(1, pc)?,
+        Command::Dec => machine.add(-1, pc)?,
+        Command::Add(delta) => machine.add(delta, pc)?,
+        Command::Clear => *machine.current() = M::Cell::default(),
+        Command::Out => machine.output(pc)?,
+        Command::In => machine.input(pc)?,
+        Command::JmpFwd(target) if machine.current().is_zero() => return Ok(target + 1),
+        Command::JmpBack(target) if !machine.current().is_zero() => return Ok(target + 1),
+        Command::JmpFwd(_) | Command::JmpBack(_) | Command::Debug => {},
+        Command::MulAdd(ref terms) => {
+            let value = *machine.current();
+            if !value.is_zero() {
+                for &(offset, factor) in terms {
+                    machine.add_product(offset, value, factor, pc)?;
+                }
+                *machine.current() = M::Cell::default();
+            }
+        },
+    }
+    Ok(pc + 1)
+}
+
+// Slice runs commands on a tape that never grows, where moving off either end
+// is a fault and the end of input leaves the cell unchanged
+struct Slice<'a, C: 'a, I: 'a, O: 'a> {
+    memory: &'a mut [C],
+    ptr: usize,
+    input: &'a mut I,
+    output: &'a mut O,
+}
+
+impl<'a, C, I, O> Slice<'a, C, I, O> where I: ByteInput {
+    fn offset(&self, delta: isize, pc: usize) -> Result<usize, Fault<I::Error>> {
+        match self.ptr.checked_add_signed(delta) {
+            Some(target) if target < self.memory.len() => Ok(target),
+            Some(target) => Err(Fault::PointerOverflow { pc, ptr: target }),
+            None => Err(Fault::PointerUnderflow { pc }),
+        }
+    }
+}
+
+impl<'a, C, I, O> Machine for Slice<'a, C, I, O> where C: Cell, I: ByteInput, O: ByteOutput<Error = I::Error> {
+    type Cell = C;
+    type Error = Fault<I::Error>;
+
+    fn current(&mut self) -> &mut C {
+        &mut self.memory[self.ptr]
+    }
+
+    fn move_by(&mut self, delta: isize, pc: usize) -> Result<(), Self::Error> {
+        self.ptr = self.offset(delta, pc)?;
+        Ok(())
+    }
+
+    fn add_product(&mut self, offset: isize, value: C, factor: i32, pc: usize) -> Result<(), Self::Error> {
+        let target = self.offset(offset, pc)?;
+        self.memory[target] = self.memory[target].add_product(value, factor);
+        Ok(())
+    }
+
+    fn output(&mut self, _pc: usize) -> Result<(), Self::Error> {
+        self.output.write_byte(self.memory[self.ptr].to_byte()).map_err(Fault::Io)
+    }
+
+    fn input(&mut self, _pc: usize) -> Result<(), Self::Error> {
+        if let Some(byte) = self.input.read_byte().map_err(Fault::Io)? {
+            self.memory[self.ptr] = C::from_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+// run executes commands on memory starting from the first cell and returns the
+// cell the pointer finished on
+pub fn run<C, I, O>(commands: &[Command], memory: &mut [C], input: &mut I, output: &mut O) -> Result<usize, Fault<I::Error>>
+        where C: Cell, I: ByteInput, O: ByteOutput<Error = I::Error> {
+    let mut commands = commands.to_vec();
+    link(&mut commands)?;
+    let mut machine = Slice { memory, ptr: 0, input, output };
+    let mut pc = 0;
+    while pc < commands.len() {
+        pc = step(&mut machine, &commands, pc)?;
+    }
+    Ok(machine.ptr)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[cfg(feature = "std")]
+    use optimize;
+
+    // Nowhere has no input and throws away output without needing std
+    struct Nowhere;
+
+    impl ByteInput for Nowhere {
+        type Error = ();
+
+        fn read_byte(&mut self) -> Result<Option<u8>, ()> {
+            Ok(None)
+        }
+    }
+
+    impl ByteOutput for Nowhere {
+        type Error = ();
+
+        fn write_byte(&mut self, _: u8) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn hello_world() {
+        let hello = b"++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+        let mut memory = [0u8; 16];
+        let mut output = Vec::new();
+        run(&compile(hello, false), &mut memory, &mut &b""[..], &mut output).unwrap();
+        assert_eq!(output, b"Hello World!\n");
+
+        // optimized commands run the same
+        let mut memory = [0u16; 16];
+        let mut output = Vec::new();
+        run(&optimize::all(compile(hello, false)), &mut memory, &mut &b""[..], &mut output).unwrap();
+        assert_eq!(output, b"Hello World!\n");
+
+        let mut memory = [0u8; 4];
+        let mut output = Vec::new();
+        assert_eq!(1, run(&compile(b",>,<.>.", false), &mut memory, &mut &b"ab"[..], &mut output).unwrap());
+        assert_eq!(output, b"ab");
+    }
+
+    #[test]
+    fn faults() {
+        let mut memory = [0u8; 4];
+        let run_on = |source: &[u8], memory: &mut [u8]| run(&compile(source, false), memory, &mut Nowhere, &mut Nowhere);
+        assert_eq!(Err(Fault::PointerUnderflow { pc: 1 }), run_on(b"+<", &mut memory));
+        assert_eq!(Err(Fault::PointerOverflow { pc: 3, ptr: 4 }), run_on(b">>>>>", &mut memory));
+        assert_eq!(Err(Fault::UnmatchedOpen(1)), run_on(b"+[[]", &mut memory));
+        assert_eq!(Err(Fault::UnmatchedClose(0)), run_on(b"]", &mut memory));
+    }
+}
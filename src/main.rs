@@ -1,64 +1,844 @@
 #[macro_use]
 extern crate clap;
+extern crate brainfuck;
 
-mod program;
-use program::Program;
+use brainfuck::analyze;
+use brainfuck::dialects::ook;
+use brainfuck::include;
+use brainfuck::optimize;
+use brainfuck::{validate_tokens, validate_with, BfError, Cell, Command, CommandMap, Diagnostic, EofMode, LineInput, Limits, Observers, OutputMode, Position, Program, RandomInput, TapeMode, DEFAULT_TAPE_SIZE};
 
-use std::io::{self, Read, BufRead, BufReader};
-use std::fs::File;
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::io::{self, Read, BufRead, BufReader, BufWriter, Write};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
+
+// with_cell calls the generic function f with the cell type picked by
+// --cell-size and --signedness
+macro_rules! with_cell {
+    ($cell:expr, $f:ident($($arg:expr),*)) => {
+        match $cell {
+            (16, false) => $f::<u16>($($arg),*),
+            (32, false) => $f::<u32>($($arg),*),
+            (_, false) => $f::<u8>($($arg),*),
+            (16, true) => $f::<i16>($($arg),*),
+            (32, true) => $f::<i32>($($arg),*),
+            (_, true) => $f::<i8>($($arg),*),
+        }
+    };
+}
 
 fn main() {
     let matches = clap_app!(bfi =>
             (version: "0.1")
             (about: "A simple brainfuck interpreter.")
-            (after_help: "If both PROGRAM and INPUT are to be read from the same source, '!' will be treated as a separator")
-            (@arg PROGRAM: +required +takes_value "Sets the program source, '-' will read the program from stdin")
+            (after_help: "If both PROGRAM and INPUT are to be read from the same source, the first '!' separates them, \
+                everything before it is the program and everything after it is the input. With --combined-format \
+                length the first line gives the length of the program in bytes instead")
+            (@arg PROGRAM: +takes_value required_unless[program_fd]
+                "Sets the program source, '-' will read the program from stdin. Given a directory every .b and .bf \
+                file in it is run and its output compared against the file with the same name ending in .out, \
+                a file ending in .in is used as its input")
             (@arg INPUT: !required +takes_value "Input file, defaults to stdin")
-            (@arg debug: -d "Enables the use of '#' as a debug print command")
+            (@arg combined_format: --("combined-format") +takes_value possible_values(&["separator", "length"])
+                "Sets how PROGRAM and INPUT are split when they are read from the same source, either at the first \
+                '!' or after the number of bytes given on the first line, defaults to separator")
+            (@arg program_fd: --("program-fd") +takes_value {is_fd} conflicts_with[PROGRAM]
+                "Reads the program from the already open file descriptor N instead of PROGRAM")
+            (@arg input_fd: --("input-fd") +takes_value {is_fd} conflicts_with[INPUT]
+                "Reads the input from the already open file descriptor N instead of INPUT")
+            (@arg random_input: --("random-input") +takes_value {is_count} conflicts_with[INPUT input_fd]
+                "Feeds the program an endless stream of pseudo-random bytes generated from SEED as its input")
+            (@arg line_input: --("line-input") conflicts_with[random_input]
+                "Reads the input a line at a time, waiting for the next line only once the program has read all \
+                of the last one")
+            (@arg includes: --includes
+                "Replaces each line of the program that reads '@include PATH' with the file at PATH, relative to the \
+                file the line is in")
+            (@arg debug: -d "Enables the use of '#' as a debug print command, which prints to stderr")
+            (@arg debug_file: --("debug-file") +takes_value requires[debug]
+                "Writes what '#' prints to a file instead of stderr")
+            (@arg commands: --commands +takes_value
+                "Reads the characters the program is written with from a file, each line gives a character \
+                followed by the standard command character it stands for")
+            (@arg strict_parse: --("strict-parse") "Stops with an error on any character that isn't a command or whitespace")
+            (@arg comment_regions: --("comment-regions") "Skips everything between '{' and '}', commands included")
+            (@arg lang: --lang +takes_value possible_values(&["brainfuck", "ook"]) conflicts_with[commands]
+                "Sets the language the program is written in, defaults to brainfuck")
+            (@arg optimize: -O --optimize "Optimizes the program before running it")
+            (@arg warn: -W --warn "Prints warnings about loops that are never entered or never end to stderr")
+            (@arg skip_dead_code: --("skip-dead-code") "Removes loops that are never entered before running the program")
+            (@arg emit: --emit +takes_value possible_values(&["c", "rust", "wat"])
+                "Prints the program translated to another language instead of running it, the tape must be fixed")
+            (@arg compare: --compare +takes_value
+                "Runs PROGRAM and the program in the given file on the same input and prints whether their outputs \
+                match, along with each byte that differs when they don't")
+            (@arg dump_ir: --("dump-ir") "Prints the optimized program instead of running it")
+            (@arg minify: --minify "Prints the program without comments or whitespace instead of running it")
+            (@arg optimize_source: --("optimize-source")
+                "Prints a shorter program that does the same thing instead of running it, this minifies the program \
+                with dead loops removed and the optimizer applied")
+            (@arg explain: --explain "Prints each command with a description of what it does instead of running it")
+            (@arg dump_tokens: --("dump-tokens")
+                "Prints the compiled commands one per line as INDEX COMMAND [OPERANDS] instead of running it")
+            (@arg eof: --eof +takes_value possible_values(&["unchanged", "zero", "all-ones"])
+                "Sets what ',' stores on end of input, defaults to unchanged")
+            (@arg checked_cells: --("checked-cells") "Stops the program with an error instead of letting a cell wrap around")
+            (@arg lenient: --lenient
+                "Keeps going when the pointer moves off either end of the tape by leaving it on the first or last \
+                cell, printing a warning to stderr")
+            (@arg strict_input: --("strict-input") "Stops the program with an error if it reads input when none was given")
+            (@arg output_mode: --("output-mode") +takes_value possible_values(&["bytes", "decimal"])
+                "Sets whether '.' prints the cell as a raw byte or as a decimal number and a space, defaults to bytes")
+            (@arg cell_size: --("cell-size") +takes_value possible_values(&["8", "16", "32"])
+                "Sets the width of a tape cell in bits, defaults to 8")
+            (@arg signedness: --signedness +takes_value possible_values(&["unsigned", "signed"])
+                "Sets whether tape cells hold signed values, defaults to unsigned")
+            (@arg tape_size: --("tape-size") +takes_value {is_tape_size}
+                "Sets the number of cells on the tape, defaults to 30000")
+            (@arg tape_mode: --("tape-mode") +takes_value possible_values(&["fixed", "growable", "bidirectional", "wrapping"])
+                "Sets whether the tape grows or wraps around when the pointer moves past either end, defaults to fixed")
+            (@arg max_steps: --("max-steps") +takes_value {is_count}
+                "Stops the program with an error after N commands have been executed")
+            (@arg timeout_ms: --("timeout-ms") +takes_value {is_count}
+                "Stops the program with an error after it has run for N milliseconds")
+            (@arg max_output: --("max-output") +takes_value {is_count}
+                "Stops the program with an error when it tries to output more than N times")
+            (@arg repeat: --repeat +takes_value {is_repeat}
+                "Runs the program N times on the same input and prints the total and average time to stderr")
+            (@arg dump_memory: --("dump-memory") +takes_value {is_range}
+                "Prints LEN cells of the tape starting at START to stderr after running, given as START:LEN")
+            (@arg visualize: --visualize "Prints the cells around the pointer to stderr after every '.'")
+            (@arg echo_input: --("echo-input") "Prints each byte of input to stdout as the program reads it")
+            (@arg histogram: --histogram
+                "Prints how many cells are zero and how many hold each other value to stderr after running")
+            (@arg annotate_output: --("annotate-output")
+                "Prints a line for each byte the program writes instead of the byte, giving the pc and source position \
+                of the '.' that wrote it")
+            (@arg profile: --profile "Prints how often each command was executed to stderr after running")
+            (@arg quiet: -q --quiet "Doesn't print the error a program stops with, the exit status still shows it failed")
+            (@arg trace: --trace "Prints every command executed along with the pc, pointer and cell value to stderr")
+            (@arg watch: --watch +takes_value +use_delimiter requires[trace] {is_count}
+                "Adds the values of a comma separated list of cells to every line of the trace")
         ).get_matches();
 
-    let program_arg = matches.value_of("PROGRAM").unwrap();
-    let input_arg = matches.value_of("INPUT").unwrap_or("-");
+    let program_arg = source_path(matches.value_of("PROGRAM"), matches.value_of("program_fd"));
+    let input_arg = source_path(matches.value_of("INPUT"), matches.value_of("input_fd"));
     let debug = matches.is_present("debug");
+    let eof_mode = match matches.value_of("eof") {
+        Some("zero") => EofMode::Zero,
+        Some("all-ones") => EofMode::AllOnes,
+        _ => EofMode::Unchanged,
+    };
+    let tape_mode = match matches.value_of("tape_mode") {
+        Some("growable") => TapeMode::Growable { max: None },
+        Some("bidirectional") => TapeMode::Bidirectional,
+        Some("wrapping") => TapeMode::Wrapping,
+        _ => TapeMode::Fixed,
+    };
+    let output_mode = match matches.value_of("output_mode") {
+        Some("decimal") => OutputMode::Decimal,
+        _ => OutputMode::Bytes,
+    };
+    let options = Options {
+        eof_mode,
+        output_mode,
+        tape_mode,
+        tape_size: matches.value_of("tape_size").map_or(DEFAULT_TAPE_SIZE, |n| n.parse().unwrap()),
+        limits: Limits {
+            max_steps: matches.value_of("max_steps").map(|n| n.parse().unwrap()),
+            timeout: matches.value_of("timeout_ms").map(|n| Duration::from_millis(n.parse().unwrap())),
+            max_output: matches.value_of("max_output").map(|n| n.parse().unwrap()),
+        },
+        emit: matches.value_of("emit").map(String::from),
+        trace: matches.is_present("trace"),
+        watch: matches.values_of("watch").map_or(Vec::new(), |cells| cells.map(|n| n.parse().unwrap()).collect()),
+        echo_input: matches.is_present("echo_input"),
+        visualize: matches.is_present("visualize"),
+        strict_input: matches.is_present("strict_input"),
+        checked_cells: matches.is_present("checked_cells"),
+        lenient: matches.is_present("lenient"),
+        debug_file: matches.value_of("debug_file").map(String::from),
+        profile: matches.is_present("profile"),
+        histogram: matches.is_present("histogram"),
+        annotate_output: matches.is_present("annotate_output"),
+        dump_memory: matches.value_of("dump_memory").map(|r| parse_range(r).unwrap()),
+        repeat: matches.value_of("repeat").map(|n| n.parse().unwrap()),
+        quiet: matches.is_present("quiet"),
+    };
+    // the translated programs only have a tape of a fixed size
+    if options.emit.is_some() && options.tape_mode != TapeMode::Fixed {
+        fail("--emit only supports the fixed tape mode");
+    }
+
+    let source = Source {
+        ook: matches.value_of("lang") == Some("ook"),
+        command_map: {
+            let command_map = match matches.value_of("commands") {
+                Some(path) => match read_command_map(path, debug) {
+                    Ok(map) => map,
+                    Err(e) => fail(e),
+                },
+                None => CommandMap::standard(debug),
+            };
+            if matches.is_present("comment_regions") {
+                command_map.with_comment_region('{', '}')
+            } else {
+                command_map
+            }
+        },
+        strict_parse: matches.is_present("strict_parse"),
+        includes: matches.is_present("includes"),
+        debug,
+    };
+    let cell = (matches.value_of("cell_size").map_or(8, |n| n.parse().unwrap()), matches.value_of("signedness") == Some("signed"));
+
+    if Path::new(&program_arg).is_dir() {
+        let passed = with_cell!(cell, run_directory(Path::new(&program_arg), &source, &options, matches.is_present("optimize")));
+        process::exit(if passed { 0 } else { 1 });
+    }
+
+    let (program, mut input) = match get_program_and_input(&program_arg, &input_arg, matches.value_of("combined_format") == Some("length")) {
+        Ok(res) => res,
+        Err(e) => fail(e),
+    };
+    if let Some(seed) = matches.value_of("random_input") {
+        input = Box::new(RandomInput::new(seed.parse().unwrap()));
+    } else if matches.is_present("line_input") {
+        input = Box::new(LineInput::new(BufReader::new(input)));
+    }
+    // a program read from stdin includes files relative to the working directory
+    let dir = match Path::new(&program_arg).parent() {
+        Some(dir) if program_arg != "-" && !program_arg.starts_with("/dev/fd/") => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let (mut commands, mut positions) = match compile_source(program, &dir, &source) {
+        Ok(compiled) => compiled,
+        Err(errors) => {
+            for e in errors {
+                eprintln!("error: {}", e);
+            }
+            process::exit(1);
+        },
+    };
+    if matches.is_present("warn") {
+        // only a fixed tape has an end the pointer can't move past
+        let tape_size = if options.tape_mode == TapeMode::Fixed { Some(options.tape_size) } else { None };
+        warn(&commands, positions.as_ref().map(|p| &p[..]), tape_size);
+    }
+    // the positions no longer line up with the commands once any are removed
+    let optimize_source = matches.is_present("optimize_source");
+    if matches.is_present("skip_dead_code") || optimize_source {
+        commands = analyze::remove_dead_loops(commands);
+        positions = None;
+    }
+    // the tape always starts zeroed here so leading loops can't be entered
+    if matches.is_present("optimize") || optimize_source {
+        commands = optimize::all(optimize::leading_loops(commands));
+        positions = None;
+    }
+    if matches.is_present("dump_ir") {
+        if let Err(e) = Program::try_new(commands.clone()) {
+            fail(e);
+        }
+        for op in optimize::optimize(commands) {
+            print!("{}", op);
+        }
+        return;
+    }
+    // optimized commands are displayed as the source they stand for
+    if matches.is_present("minify") || optimize_source {
+        match Program::try_new(commands) {
+            Ok(prog) => println!("{}", prog),
+            Err(e) => fail(e),
+        }
+        return;
+    }
+    if matches.is_present("explain") {
+        match Program::try_new(commands) {
+            Ok(prog) => print!("{}", prog.explain()),
+            Err(e) => fail(e),
+        }
+        return;
+    }
+    if matches.is_present("dump_tokens") {
+        match Program::try_new(commands) {
+            Ok(prog) => print!("{}", prog.listing()),
+            Err(e) => fail(e),
+        }
+        return;
+    }
+    if let Some(other) = matches.value_of("compare") {
+        let mut sample = Vec::new();
+        if let Err(e) = input.read_to_end(&mut sample) {
+            fail(format!("could not read INPUT: {}", e));
+        }
+        match with_cell!(cell, compare(commands, Path::new(other), &source, &options, matches.is_present("optimize"), &sample)) {
+            Ok(true) => return,
+            Ok(false) => process::exit(1),
+            Err(e) => fail(e),
+        }
+    }
+    let ran = with_cell!(cell, execute(commands, positions, &options, &mut input));
+    if !ran {
+        process::exit(1);
+    }
+}
+
+// Source holds the settings for turning the text of a program into commands
+struct Source {
+    ook: bool,
+    command_map: CommandMap,
+    strict_parse: bool,
+    includes: bool,
+    debug: bool,
+}
+
+impl Source {
+    // streams is whether programs can be compiled as they are read, anything
+    // but plain brainfuck needs the whole text
+    fn streams(&self) -> bool {
+        !self.ook && !self.includes && !self.strict_parse && self.command_map == CommandMap::standard(self.debug)
+    }
+}
+
+// Options holds the settings applied to the program before it is run
+struct Options {
+    eof_mode: EofMode,
+    output_mode: OutputMode,
+    tape_mode: TapeMode,
+    tape_size: usize,
+    limits: Limits,
+    emit: Option<String>,
+    trace: bool,
+    watch: Vec<usize>,
+    echo_input: bool,
+    visualize: bool,
+    debug_file: Option<String>,
+    strict_input: bool,
+    checked_cells: bool,
+    lenient: bool,
+    profile: bool,
+    histogram: bool,
+    annotate_output: bool,
+    dump_memory: Option<(usize, usize)>,
+    repeat: Option<u32>,
+    quiet: bool,
+}
+
+fn is_tape_size(arg: String) -> Result<(), String> {
+    match arg.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(()),
+        _ => Err(String::from("tape size must be a positive integer")),
+    }
+}
+
+fn is_count(arg: String) -> Result<(), String> {
+    arg.parse::<u64>().map(|_| ()).map_err(|_| String::from("expected a non-negative integer"))
+}
+
+fn is_repeat(arg: String) -> Result<(), String> {
+    match arg.parse::<u32>() {
+        Ok(n) if n > 0 => Ok(()),
+        _ => Err(String::from("repeat count must be a positive integer")),
+    }
+}
+
+fn is_fd(arg: String) -> Result<(), String> {
+    arg.parse::<u32>().map(|_| ()).map_err(|_| String::from("expected a file descriptor number"))
+}
+
+fn is_range(arg: String) -> Result<(), String> {
+    parse_range(&arg).map(|_| ())
+}
+
+// parse a START:LEN pair of cell counts
+fn parse_range(arg: &str) -> Result<(usize, usize), String> {
+    let err = || String::from("expected START:LEN with non-negative integers");
+    let mut parts = arg.splitn(2, ':');
+    let start = parts.next().and_then(|n| n.parse().ok()).ok_or_else(err)?;
+    let len = parts.next().and_then(|n| n.parse().ok()).ok_or_else(err)?;
+    Ok((start, len))
+}
+
+// execute sets up and runs the program, it returns false if the program
+// stopped with an error
+fn execute<C: Cell>(commands: Vec<Command>, positions: Option<Vec<Position>>, options: &Options, input: &mut Box<dyn Read>) -> bool {
+    let mut prog = match Program::<C>::try_with_tape_size(commands, options.tape_size) {
+        Ok(prog) => prog,
+        Err(e) => fail(e),
+    };
+    if let Some(positions) = positions {
+        prog.set_positions(positions);
+    }
+    configure(&mut prog, options);
+    if options.profile {
+        prog.enable_profiling();
+    }
+
+    if let Some(ref lang) = options.emit {
+        match lang.as_str() {
+            "c" => print!("{}", prog.to_c()),
+            "rust" => print!("{}", prog.to_rust()),
+            "wat" => print!("{}", prog.to_wat()),
+            _ => unreachable!(),
+        }
+        return true;
+    }
+
+    // everything for stderr goes through one buffer so it comes out in the
+    // order it happened
+    let stderr = io::stderr();
+    let diagnostics = RefCell::new(BufWriter::new(stderr.lock()));
+    let mut observers = Observers::default();
+    let mut trace = Shared(&diagnostics);
+    if options.trace {
+        observers.trace = Some(&mut trace);
+    }
+    let mut echo = io::stdout();
+    if options.echo_input {
+        observers.echo = Some(&mut echo);
+    }
+    let mut tape = Shared(&diagnostics);
+    if options.visualize {
+        observers.tape = Some(&mut tape);
+    }
+    let mut warnings = Shared(&diagnostics);
+    observers.warnings = Some(&mut warnings);
+    // the annotations take the place of the program's own output
+    let mut annotated = io::stdout();
+    let mut output: Box<dyn Write> = if options.annotate_output {
+        observers.outputs = Some(&mut annotated);
+        Box::new(io::sink())
+    } else {
+        Box::new(io::stdout())
+    };
+    let mut debug_file = match options.debug_file {
+        Some(ref path) => match File::create(path) {
+            Ok(file) => Some(BufWriter::new(file)),
+            Err(e) => fail(format!("couldn't create {}: {}", path, e)),
+        },
+        None => None,
+    };
+    if let Some(ref mut file) = debug_file {
+        observers.debug = Some(file);
+    }
+    let result = match options.repeat {
+        Some(times) => run_repeated(&mut prog, times, input, &mut output, &options.limits, &mut observers)
+            .map(|elapsed| Some((times, elapsed))),
+        None => prog.run_with(input, &mut output, &options.limits, &mut observers).map(|_| None),
+    };
+    let mut diagnostics = diagnostics.borrow_mut();
+    match result {
+        Ok(Some((times, elapsed))) => {
+            let _ = writeln!(diagnostics, "ran {} times in {:?}, {:?} on average", times, elapsed, elapsed / times);
+        },
+        Ok(None) => {},
+        Err(ref e) if !options.quiet => {
+            let _ = writeln!(diagnostics, "error: {}", e);
+        },
+        Err(_) => {},
+    }
+    if let Some((start, len)) = options.dump_memory {
+        let _ = write!(diagnostics, "{}", prog.dump_memory(start, len));
+    }
+    if options.profile {
+        let _ = write!(diagnostics, "{}", prog.profile_summary());
+    }
+    if options.histogram {
+        let _ = write!(diagnostics, "{}", prog.histogram_summary());
+    }
+    result.is_ok()
+}
+
+// Shared lets several observers write to the same stream
+struct Shared<'a, W: 'a>(&'a RefCell<W>);
+
+impl<'a, W: Write> Write for Shared<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+// configure applies the settings that change how the program runs
+fn configure<C: Cell>(prog: &mut Program<C>, options: &Options) {
+    prog.set_eof_mode(options.eof_mode);
+    prog.set_output_mode(options.output_mode);
+    prog.set_tape_mode(options.tape_mode);
+    prog.set_strict_input(options.strict_input);
+    prog.set_checked_cells(options.checked_cells);
+    prog.set_lenient(options.lenient);
+    prog.watch(&options.watch);
+}
+
+// run_directory runs every program in dir against the input next to it and
+// compares what it prints with the expected output, printing a line for each
+// program and a summary. It returns whether every program passed, programs
+// without an expected output are skipped.
+fn run_directory<C: Cell>(dir: &Path, source: &Source, options: &Options, optimize: bool) -> bool {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => fail(format!("could not read directory {}: {}", dir.display(), e)),
+    };
+    let mut programs: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "b" || ext == "bf"))
+        .collect();
+    programs.sort();
+
+    let (mut passed, mut failed) = (0, 0);
+    for path in programs {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let expected = match fs::read(path.with_extension("out")) {
+            Ok(expected) => expected,
+            Err(_) => {
+                println!("SKIP {}: no expected output", name);
+                continue;
+            },
+        };
+        match run_file::<C>(&path, source, options, optimize) {
+            Ok(ref output) if *output == expected => {
+                println!("PASS {}", name);
+                passed += 1;
+            },
+            Ok(_) => {
+                println!("FAIL {}: output differs", name);
+                failed += 1;
+            },
+            Err(e) => {
+                println!("FAIL {}: {}", name, e);
+                failed += 1;
+            },
+        }
+    }
+    println!("{} passed, {} failed", passed, failed);
+    failed == 0
+}
 
-    let (program_raw, mut input) = get_program_and_input(&program_arg, &input_arg).unwrap();
-    let mut prog = Program::new(Program::compile(&program_raw, debug));
+// run_file runs one program of a directory with its input, if it has any
+fn run_file<C: Cell>(path: &Path, source: &Source, options: &Options, optimize: bool) -> Result<Vec<u8>, String> {
+    let mut prog = load_file::<C>(path, source, options, optimize)?;
+    let input = fs::read(path.with_extension("in")).unwrap_or_default();
+    run_warning(&mut prog, &input, &options.limits).map_err(|e| e.to_string())
+}
 
-    let mut output = io::stdout();
-    match prog.run(&mut input, &mut output) {
-        Ok(_) => {},
-        Err(e) => print!("Error occurred during execution: {:?}", e),
+// run_warning runs prog on input and gives what it wrote, lenient mode warnings
+// go to stderr
+fn run_warning<C: Cell>(prog: &mut Program<C>, input: &[u8], limits: &Limits) -> Result<Vec<u8>, BfError> {
+    let mut output = Vec::new();
+    let mut observers = Observers { warnings: Some(&mut io::stderr()), ..Observers::default() };
+    prog.run_with(&mut &input[..], &mut output, limits, &mut observers)?;
+    Ok(output)
+}
+
+// load_file compiles the program in the file at path and configures it like PROGRAM
+fn load_file<C: Cell>(path: &Path, source: &Source, options: &Options, optimize: bool) -> Result<Program<C>, String> {
+    let file = File::open(path).map_err(|e| format!("could not read program: {}", e))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let (mut commands, mut positions) = compile_source(BufReader::new(file), dir, source).map_err(|errors| errors.join(", "))?;
+    if optimize {
+        commands = optimize::all(optimize::leading_loops(commands));
+        positions = None;
+    }
+    let mut prog = Program::try_with_tape_size(commands, options.tape_size).map_err(|e| e.to_string())?;
+    if let Some(positions) = positions {
+        prog.set_positions(positions);
     }
+    configure(&mut prog, options);
+    Ok(prog)
 }
 
-fn get_program_and_input(prog_arg: &str, input_arg: &str) -> io::Result<(String, Box<Read>)> {
+// Compiled is the commands of a program with their positions in the source
+type Compiled = (Vec<Command>, Option<Vec<Position>>);
+
+// compile_source reads a program and turns it into commands, expanding includes
+// relative to dir, commands compiled from brainfuck come with their positions.
+// Plain brainfuck is compiled as it is read so the source is never held in
+// memory. Every problem with the source is given when it can't be compiled.
+fn compile_source<R: BufRead>(mut reader: R, dir: &Path, source: &Source) -> Result<Compiled, Vec<String>> {
+    let unreadable = |e: io::Error| vec![format!("could not read program: {}", e)];
+    let invalid = |diagnostics: Vec<Diagnostic>| diagnostics.iter().map(|d| d.to_string()).collect::<Vec<_>>();
+    if source.streams() {
+        let tokens = Program::tokenize_reader(reader, source.debug).map_err(unreadable)?;
+        validate_tokens(&tokens).map_err(invalid)?;
+        let (commands, positions) = tokens.into_iter().unzip();
+        return Ok((commands, Some(positions)));
+    }
+
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes).map_err(unreadable)?;
+    // only a few ASCII characters mean anything so bytes that aren't valid
+    // UTF-8 are left in as comments
+    let mut raw = String::from_utf8_lossy(&bytes);
+    if source.includes {
+        raw = include::expand(&raw, dir).map_err(|e| vec![e.to_string()])?.into();
+    }
+    if source.ook {
+        return Ok((ook::compile(&raw), None));
+    }
+    validate_with(&raw, &source.command_map, source.strict_parse).map_err(invalid)?;
+    let (commands, positions) = Program::tokenize_with(&raw, &source.command_map).into_iter().unzip();
+    Ok((commands, Some(positions)))
+}
+
+// compare runs PROGRAM and the program in the file at other on the same input
+// and reports whether they wrote the same output, listing every byte that
+// differs when they didn't
+fn compare<C: Cell>(commands: Vec<Command>, other: &Path, source: &Source, options: &Options, optimize: bool, input: &[u8]) -> Result<bool, String> {
+    let mut first = Program::<C>::try_with_tape_size(commands, options.tape_size).map_err(|e| e.to_string())?;
+    configure(&mut first, options);
+    let mut second = load_file::<C>(other, source, options, optimize)?;
+    let first = run_warning(&mut first, input, &Limits::default()).map_err(|e| format!("PROGRAM failed: {}", e))?;
+    let second = run_warning(&mut second, input, &Limits::default()).map_err(|e| format!("second program failed: {}", e))?;
+    if first == second {
+        println!("outputs match ({} bytes)", first.len());
+        return Ok(true);
+    }
+
+    for (i, (a, b)) in first.iter().zip(&second).enumerate() {
+        if a != b {
+            println!("byte {}: {} != {}", i, a, b);
+        }
+    }
+    if first.len() != second.len() {
+        println!("lengths differ: PROGRAM wrote {} bytes, the second program wrote {}", first.len(), second.len());
+    }
+    println!("outputs differ");
+    Ok(false)
+}
+
+// run_repeated runs the program from a fresh tape the given number of times and
+// returns how long the runs took altogether, the input is read up front so
+// every run sees all of it
+fn run_repeated<C: Cell, W: Write>(prog: &mut Program<C>, times: u32, input: &mut Box<dyn Read>, output: &mut W,
+        limits: &Limits, observers: &mut Observers) -> Result<Duration, BfError> {
+    let mut buffered = Vec::new();
+    input.read_to_end(&mut buffered)?;
+
+    let mut elapsed = Duration::default();
+    for _ in 0..times {
+        prog.reset();
+        let start = Instant::now();
+        prog.run_with(&mut &buffered[..], output, limits, observers)?;
+        elapsed += start.elapsed();
+    }
+    Ok(elapsed)
+}
+
+// read_command_map reads a file of lines like "a +" that each make the first
+// character stand for the standard command given by the second, '#' is still
+// the debug command when debug is set unless the file maps it to something else
+fn read_command_map(path: &str, debug: bool) -> Result<CommandMap, String> {
+    let mut config = String::new();
+    open(path, "commands")?.read_to_string(&mut config).map_err(|e| format!("could not read commands file: {}", e))?;
+
+    let standard = CommandMap::standard(true);
+    let mut map = if debug { CommandMap::empty().with('#', Command::Debug) } else { CommandMap::empty() };
+    for (i, line) in config.lines().enumerate() {
+        let chars: Vec<char> = line.split_whitespace().flat_map(|word| word.chars()).collect();
+        let entry = match chars[..] {
+            [] => continue,
+            [c, standard_char] => standard.get(standard_char).map(|command| (c, command.clone())),
+            _ => None,
+        };
+        match entry {
+            Some((c, command)) => map.insert(c, command),
+            None => return Err(format!("line {} of the commands file should be a character and a command", i + 1)),
+        }
+    }
+    Ok(map)
+}
+
+// print the analysis warnings for the program, pointing into the source when
+// the positions of the commands are known, moves are checked against the tape
+// when it has a fixed size
+fn warn(commands: &[Command], positions: Option<&[Position]>, tape_size: Option<usize>) {
+    let overflow = tape_size.and_then(|tape_size| analyze::check_tape(commands, tape_size));
+    for warning in analyze::check(commands).into_iter().chain(overflow) {
+        match positions {
+            Some(positions) => {
+                let pos = positions[warning.start()];
+                eprintln!("warning: {} at line {}, column {}", warning.message(), pos.line, pos.column);
+            },
+            None => eprintln!("warning: {}", warning),
+        }
+    }
+}
+
+// print a message for an error that stops the program from running at all and exit
+fn fail<E: Display>(e: E) -> ! {
+    eprintln!("error: {}", e);
+    process::exit(1);
+}
+
+// source_path resolves where the program or input is read from, a file
+// descriptor is opened through /dev/fd so it is read like any other file and
+// with neither given the source is stdin
+fn source_path(path: Option<&str>, fd: Option<&str>) -> String {
+    match (path, fd) {
+        (_, Some(fd)) => format!("/dev/fd/{}", fd),
+        (Some(path), None) => String::from(path),
+        (None, None) => String::from("-"),
+    }
+}
+
+// Sources is where the program and its input are read from
+type Sources = (Box<dyn BufRead>, Box<dyn Read>);
+
+// with length_header a source shared by the program and input starts with the
+// length of the program instead of marking its end with '!'. A program with a
+// source of its own is given as a reader so it can be compiled as it is read.
+fn get_program_and_input(prog_arg: &str, input_arg: &str, length_header: bool) -> Result<Sources, String> {
     if prog_arg == input_arg {
-        // read input until '!' for program, rest is for input
+        // read input until '!' or the given length for program, rest is for input
         let input = if input_arg == "-" {
-            Box::new(io::stdin()) as Box<Read>
+            Box::new(io::stdin()) as Box<dyn Read>
         } else {
-            Box::new(File::open(prog_arg)?) as Box<Read>
+            Box::new(open(prog_arg, "PROGRAM")?) as Box<dyn Read>
         };
-        let mut buf = Vec::new();
         let mut buffered = BufReader::new(input);
-        buffered.read_until('!' as u8, &mut buf)?;
+        let prog = if length_header {
+            read_with_length(&mut buffered)
+        } else {
+            read_to_separator(&mut buffered)
+        };
+        let prog = prog.map_err(|e| format!("could not read PROGRAM: {}", e))?;
 
-        Ok((String::from_utf8(buf).unwrap(), Box::new(buffered) as Box<Read>))
+        Ok((Box::new(io::Cursor::new(prog)), Box::new(buffered) as Box<dyn Read>))
     } else {
-        let mut prog = String::new();
-        if prog_arg == "-" {
-            io::stdin().read_to_string(&mut prog)?;
+        let prog = if prog_arg == "-" {
+            Box::new(BufReader::new(io::stdin())) as Box<dyn BufRead>
         } else {
-            File::open(prog_arg)?.read_to_string(&mut prog)?;
+            Box::new(BufReader::new(open(prog_arg, "PROGRAM")?)) as Box<dyn BufRead>
         };
 
         let input = if input_arg == "-" {
-            Box::new(io::stdin()) as Box<Read>
+            Box::new(io::stdin()) as Box<dyn Read>
         } else {
-            Box::new(File::open(input_arg)?) as Box<Read>
+            Box::new(open(input_arg, "INPUT")?) as Box<dyn Read>
         };
 
         Ok((prog, input))
     }
 }
 
+// read the program from a source shared with the input, the program ends at the
+// first '!' and the input starts right after it so the separator is in neither,
+// without a '!' the whole source is the program
+fn read_to_separator<R: BufRead>(source: &mut R) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    source.read_until(b'!', &mut buf)?;
+    if buf.last() == Some(&b'!') {
+        buf.pop();
+    }
+    Ok(buf)
+}
+
+// read the program from a source shared with the input that starts with a line
+// holding the length of the program in bytes, the input is everything after
+// the program so any byte can appear in either
+fn read_with_length<R: BufRead>(source: &mut R) -> io::Result<Vec<u8>> {
+    let mut header = String::new();
+    source.read_line(&mut header)?;
+    let len: usize = header.trim_end().parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("invalid length header {:?}", header.trim_end())))?;
+    let mut buf = vec![0; len];
+    source.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn open(path: &str, what: &str) -> Result<File, String> {
+    File::open(path).map_err(|e| format!("could not open {} file: {}", what, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::io::Cursor;
+
+    #[test]
+    fn separator_stdin() {
+        let mut source = Cursor::new(b",[.,]!ab!c".to_vec());
+        assert_eq!(read_to_separator(&mut source).unwrap(), b",[.,]");
+        let mut rest = Vec::new();
+        source.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"ab!c");
+
+        let mut source = Cursor::new(b"+.".to_vec());
+        assert_eq!(read_to_separator(&mut source).unwrap(), b"+.");
+
+        let mut source = Cursor::new(b"!".to_vec());
+        assert_eq!(read_to_separator(&mut source).unwrap(), b"");
+    }
+
+    #[test]
+    fn source_paths() {
+        assert_eq!(source_path(Some("prog.b"), None), "prog.b");
+        assert_eq!(source_path(None, Some("3")), "/dev/fd/3");
+        assert_eq!(source_path(None, None), "-");
+
+        // separate descriptors are separate sources so there is no '!' to look for
+        let program = source_path(None, Some("3"));
+        let input = source_path(None, Some("4"));
+        assert_ne!(program, input);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn separate_fds() {
+        let path = env::temp_dir().join(format!("brainfuck-fds-{}.b", process::id()));
+        fs::write(&path, ",.!ignored").unwrap();
+        let file = fs::File::open(&path).unwrap();
+        let fd = format!("{}", std::os::unix::io::AsRawFd::as_raw_fd(&file));
+
+        let (mut prog, _) = get_program_and_input(&source_path(None, Some(&fd)), "-", false).unwrap();
+        let mut source = Vec::new();
+        prog.read_to_end(&mut source).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(source, b",.!ignored");
+    }
+
+    #[test]
+    fn separator_file() {
+        let path = env::temp_dir().join(format!("brainfuck-separator-{}.b", process::id()));
+        fs::write(&path, ",[.,]\n!hi\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        let (mut prog, mut input) = get_program_and_input(path, path, false).unwrap();
+        let mut source = Vec::new();
+        prog.read_to_end(&mut source).unwrap();
+        let mut rest = Vec::new();
+        input.read_to_end(&mut rest).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(source, b",[.,]\n");
+        assert_eq!(rest, b"hi\n");
+    }
+
+    #[test]
+    fn length_header() {
+        let path = env::temp_dir().join(format!("brainfuck-length-{}.b", process::id()));
+        fs::write(&path, b"8\n,[.,]! !\n!\x00\xff").unwrap();
+        let path = path.to_str().unwrap();
+
+        let (mut prog, mut input) = get_program_and_input(path, path, true).unwrap();
+        let mut source = Vec::new();
+        prog.read_to_end(&mut source).unwrap();
+        let mut rest = Vec::new();
+        input.read_to_end(&mut rest).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(source, b",[.,]! !");
+        assert_eq!(rest, b"\n!\x00\xff");
+
+        assert!(read_with_length(&mut Cursor::new(b"ten\n+.".to_vec())).is_err());
+        assert!(read_with_length(&mut Cursor::new(b"10\n+.".to_vec())).is_err());
+    }
+}
@@ -0,0 +1,450 @@
+// Optimization passes rewrite a compiled command stream into an equivalent but
+// faster one. They work on the output of Program::compile, before the jump
+// table is built, so the result is passed to Program::new as usual.
+
+use std::fmt;
+
+use cell::Cell;
+use command::Command;
+
+// OpIR is a structured form of an optimized program where loops hold their
+// bodies rather than jumping, it is meant for inspecting what will run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpIR {
+    Add(i32),
+    Move(isize),
+    Clear,
+    MulAdd(Vec<(isize, i32)>),
+    Out,
+    In,
+    Debug,
+    Loop(Vec<OpIR>),
+}
+
+impl OpIR {
+    fn fmt_indented(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        let indent = depth * 2;
+        match *self {
+            OpIR::Add(n) => writeln!(f, "{:indent$}add {}", "", n, indent = indent),
+            OpIR::Move(n) => writeln!(f, "{:indent$}move {}", "", n, indent = indent),
+            OpIR::Clear => writeln!(f, "{:indent$}clear", "", indent = indent),
+            OpIR::MulAdd(ref terms) => {
+                write!(f, "{:indent$}muladd", "", indent = indent)?;
+                for &(offset, factor) in terms {
+                    write!(f, " {}:{}", offset, factor)?;
+                }
+                writeln!(f)
+            },
+            OpIR::Out => writeln!(f, "{:indent$}out", "", indent = indent),
+            OpIR::In => writeln!(f, "{:indent$}in", "", indent = indent),
+            OpIR::Debug => writeln!(f, "{:indent$}debug", "", indent = indent),
+            OpIR::Loop(ref body) => {
+                writeln!(f, "{:indent$}loop", "", indent = indent)?;
+                for op in body {
+                    op.fmt_indented(f, depth + 1)?;
+                }
+                writeln!(f, "{:indent$}end", "", indent = indent)
+            },
+        }
+    }
+}
+
+// each op is printed on its own line, with loop bodies indented
+impl fmt::Display for OpIR {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_indented(f, 0)
+    }
+}
+
+// optimize runs every pass over commands and lifts the result into OpIR,
+// it panics if the brackets in commands are unbalanced
+pub fn optimize(commands: Vec<Command>) -> Vec<OpIR> {
+    let mut stack = vec![Vec::new()];
+    for command in all(commands) {
+        let op = match command {
+            Command::Right => OpIR::Move(1),
+            Command::Left => OpIR::Move(-1),
+            Command::Inc => OpIR::Add(1),
+            Command::Dec => OpIR::Add(-1),
+            Command::Add(n) => OpIR::Add(n),
+            Command::Move(n) => OpIR::Move(n),
+            Command::Clear => OpIR::Clear,
+            Command::MulAdd(terms) => OpIR::MulAdd(terms),
+            Command::Out => OpIR::Out,
+            Command::In => OpIR::In,
+            Command::Debug => OpIR::Debug,
+            Command::JmpFwd(_) => {
+                stack.push(Vec::new());
+                continue;
+            },
+            Command::JmpBack(_) => {
+                let body = stack.pop().unwrap();
+                OpIR::Loop(body)
+            },
+        };
+        stack.last_mut().expect("unmatched ']'").push(op);
+    }
+    assert!(stack.len() == 1, "unmatched '['");
+
+    stack.pop().unwrap()
+}
+
+// all runs every pass that holds whatever the tape starts out as, in an order
+// where each one sees the output it expects. leading_loops isn't one of them.
+pub fn all(commands: Vec<Command>) -> Vec<Command> {
+    mul_loops(clear_loops(coalesce(commands)))
+}
+
+// leading_loops removes the loops at the very start of the program, which can
+// never be entered when the tape starts out zeroed. It is only safe for a
+// program that doesn't start from preloaded memory, so all leaves it out.
+pub fn leading_loops(commands: Vec<Command>) -> Vec<Command> {
+    let mut skip = 0;
+    let mut depth = 0;
+    for (pc, command) in commands.iter().enumerate() {
+        match *command {
+            Command::JmpFwd(_) => depth += 1,
+            Command::JmpBack(_) if depth > 0 => depth -= 1,
+            _ if depth == 0 => break,
+            _ => {},
+        }
+        if depth == 0 {
+            skip = pc + 1;
+        }
+    }
+
+    commands.into_iter().skip(skip).collect()
+}
+
+// coalesce merges runs of '+'/'-' into a single Add and runs of '<'/'>'
+// into a single Move, runs that cancel out are removed entirely
+pub fn coalesce(commands: Vec<Command>) -> Vec<Command> {
+    let mut out: Vec<Command> = Vec::with_capacity(commands.len());
+    for command in commands {
+        let command = match command {
+            Command::Inc => Command::Add(1),
+            Command::Dec => Command::Add(-1),
+            Command::Right => Command::Move(1),
+            Command::Left => Command::Move(-1),
+            other => other,
+        };
+
+        let merged = match (out.last(), &command) {
+            (Some(&Command::Add(a)), &Command::Add(b)) => Some(Command::Add(a.wrapping_add(b))),
+            (Some(&Command::Move(a)), &Command::Move(b)) => Some(Command::Move(a + b)),
+            _ => None,
+        };
+        match merged {
+            Some(Command::Add(0)) | Some(Command::Move(0)) => {
+                out.pop();
+            },
+            Some(merged) => *out.last_mut().unwrap() = merged,
+            None => out.push(command),
+        }
+    }
+
+    out
+}
+
+// clear_loops replaces the loops '[-]' and '[+]' with a single Clear, only a
+// loop holding nothing but one '-' or '+' is replaced
+pub fn clear_loops(commands: Vec<Command>) -> Vec<Command> {
+    let mut out = Vec::with_capacity(commands.len());
+    for command in commands {
+        let is_clear = matches!(command, Command::JmpBack(_)) && match out[..] {
+            [.., Command::JmpFwd(_), ref body] => {
+                matches!(*body, Command::Inc | Command::Dec | Command::Add(1) | Command::Add(-1))
+            },
+            _ => false,
+        };
+        if is_clear {
+            out.truncate(out.len() - 2);
+            out.push(Command::Clear);
+        } else {
+            out.push(command);
+        }
+    }
+
+    out
+}
+
+// mul_loops replaces loops that only add multiples of the current cell to
+// other cells with a single MulAdd. A loop qualifies when it holds nothing but
+// moves and arithmetic, returns the pointer to where it started, and
+// decrements the current cell by exactly one per iteration.
+pub fn mul_loops(commands: Vec<Command>) -> Vec<Command> {
+    let mut out = Vec::with_capacity(commands.len());
+    // index in out of the innermost '[' with no loop or IO since
+    let mut open = None;
+    for command in commands {
+        match command {
+            Command::JmpFwd(_) => open = Some(out.len()),
+            Command::JmpBack(_) => if let Some(start) = open.take() {
+                if let Some(terms) = mul_terms(&out[start + 1..]) {
+                    out.truncate(start);
+                    out.push(if terms.is_empty() { Command::Clear } else { Command::MulAdd(terms) });
+                    continue;
+                }
+            },
+            Command::Right | Command::Left | Command::Inc | Command::Dec
+                | Command::Move(_) | Command::Add(_) => {},
+            _ => open = None,
+        }
+        out.push(command);
+    }
+
+    out
+}
+
+// Folded is a program whose start has been worked out ahead of time by
+// fold_prefix, it carries on with commands from memory with the pointer on ptr
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Folded<C = u8> {
+    pub memory: Vec<C>,
+    pub ptr: usize,
+    pub commands: Vec<Command>,
+}
+
+// fold_prefix runs the commands at the start of the program that only move
+// the pointer and change cells on a zeroed tape of tape_size cells, leaving
+// the rest of the program to run from there. It stops at the first loop,
+// input, output or debug command and before any move off the tape, so the
+// error for that still happens when the program runs. Cells wrap as usual.
+// Nothing is folded on a tape without any cells.
+pub fn fold_prefix<C: Cell>(mut commands: Vec<Command>, tape_size: usize) -> Folded<C> {
+    if tape_size == 0 {
+        return Folded { memory: Vec::new(), ptr: 0, commands };
+    }
+    let mut memory = vec![C::default(); tape_size];
+    let mut ptr = 0;
+    let mut folded = 0;
+    for command in &commands {
+        let step = match *command {
+            Command::Right => 1,
+            Command::Left => -1,
+            Command::Move(n) => n,
+            Command::Inc => { memory[ptr] = memory[ptr].wrapping_inc(); 0 },
+            Command::Dec => { memory[ptr] = memory[ptr].wrapping_dec(); 0 },
+            Command::Add(n) => { memory[ptr] = memory[ptr].add_delta(n); 0 },
+            Command::Clear => { memory[ptr] = C::default(); 0 },
+            _ => break,
+        };
+        match ptr.checked_add_signed(step) {
+            Some(target) if target < tape_size => ptr = target,
+            _ => break,
+        }
+        folded += 1;
+    }
+
+    let rest = commands.split_off(folded);
+    Folded { memory, ptr, commands: rest }
+}
+
+// mul_terms works out the (offset, factor) pairs of a loop body made of
+// moves and arithmetic, or None when it is not a multiply loop
+fn mul_terms(body: &[Command]) -> Option<Vec<(isize, i32)>> {
+    let mut offset = 0;
+    let mut terms: Vec<(isize, i32)> = Vec::new();
+    let mut current = 0i32;
+    for command in body {
+        let (step, delta) = match *command {
+            Command::Right => (1, 0),
+            Command::Left => (-1, 0),
+            Command::Move(n) => (n, 0),
+            Command::Inc => (0, 1),
+            Command::Dec => (0, -1),
+            Command::Add(n) => (0, n),
+            _ => return None,
+        };
+        offset += step;
+        if delta == 0 {
+            continue;
+        }
+        if offset == 0 {
+            current = current.wrapping_add(delta);
+        } else if let Some(term) = terms.iter_mut().find(|t| t.0 == offset) {
+            term.1 = term.1.wrapping_add(delta);
+        } else {
+            terms.push((offset, delta));
+        }
+    }
+    if offset != 0 || current != -1 {
+        return None;
+    }
+    terms.retain(|t| t.1 != 0);
+
+    Some(terms)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use program::Program;
+    use std::io::empty;
+
+    fn run(commands: Vec<Command>) -> Vec<u8> {
+        let mut output = Vec::new();
+        Program::new(commands).run(&mut empty(), &mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn fold_start() {
+        let source = "+++>++<-->>>+++++[-]-<+<<+.>[->+<]";
+        let folded = fold_prefix::<u8>(clear_loops(coalesce(Program::compile(source, false))), 8);
+        assert_eq!(folded.commands, coalesce(Program::compile(".>[->+<]", false)));
+
+        // the folded start leaves the same tape as running it
+        let mut prefix: Program = Program::with_tape_size(Program::compile("+++>++<-->>>+++++[-]-<+<<+", false), 8);
+        prefix.run(&mut empty(), &mut Vec::new()).unwrap();
+        assert_eq!(&folded.memory[..], prefix.memory());
+        assert_eq!(folded.ptr, prefix.stats().final_ptr);
+
+        let mut whole: Program = Program::with_tape_size(Program::compile(source, false), 8);
+        let mut rest = Program::from_folded(folded).unwrap();
+        for _ in 0..2 {
+            let mut expected = Vec::new();
+            whole.run(&mut empty(), &mut expected).unwrap();
+            let mut output = Vec::new();
+            rest.run(&mut empty(), &mut output).unwrap();
+            assert_eq!(expected, output);
+            assert_eq!(whole.memory(), rest.memory());
+            whole.reset();
+            rest.reset();
+        }
+
+        // a move off the tape is left to fail when the program runs
+        let folded = fold_prefix::<u8>(Program::compile("+>+<<+", false), 8);
+        assert_eq!(folded.commands, Program::compile("<+", false));
+        let folded = fold_prefix::<u8>(Program::compile(">>+", false), 2);
+        assert_eq!(folded.commands, Program::compile(">+", false));
+
+        let folded = fold_prefix::<u8>(Program::compile("+>+", false), 0);
+        assert_eq!(folded, Folded { memory: Vec::new(), ptr: 0, commands: Program::compile("+>+", false) });
+    }
+
+    #[test]
+    fn coalesce_runs() {
+        let commands = coalesce(Program::compile("+++>>-< +- ><[-]", false));
+        let expected = vec![
+            Command::Add(3), Command::Move(2), Command::Add(-1), Command::Move(-1),
+            Command::JmpFwd(0), Command::Add(-1), Command::JmpBack(0),
+        ];
+        assert_eq!(expected, commands);
+    }
+
+    #[test]
+    fn coalesce_same_output() {
+        let programs = [
+            "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.",
+            "++++[>++++++<-]>[>+++++>+++++++<<-]>>++++<[[>[[>>+<<-]<]>>>-]>-[>+>+<<-]>]+++++[>+++++++<<++>-]>.<<.",
+            r#"[]++++++++++[>>+>+>++++++[<<+<+++>>>-]<<<<-]"A*$";?@![#>>+<<]>[>>]<<<<[>++<[-]]>.>."#,
+        ];
+        for raw in programs.iter() {
+            let commands = Program::compile(raw, false);
+            let optimized = coalesce(commands.clone());
+            assert!(optimized.len() < commands.len());
+            assert_eq!(run(commands), run(optimized));
+        }
+    }
+
+    #[test]
+    fn clear_loop_pattern() {
+        let commands = clear_loops(Program::compile("[-][+]>[--][->][[-]]", false));
+        let expected = vec![
+            Command::Clear, Command::Clear, Command::Right,
+            Command::JmpFwd(0), Command::Dec, Command::Dec, Command::JmpBack(0),
+            Command::JmpFwd(0), Command::Dec, Command::Right, Command::JmpBack(0),
+            Command::JmpFwd(0), Command::Clear, Command::JmpBack(0),
+        ];
+        assert_eq!(expected, commands);
+
+        assert_eq!(vec![Command::Add(1), Command::Clear], all(Program::compile("+[-]", false)));
+    }
+
+    #[test]
+    fn clear_loop_same_output() {
+        let raw = "+++++.[-].>++.[+].<.>--[-]+.";
+        let commands = Program::compile(raw, false);
+        let optimized = clear_loops(commands.clone());
+        assert_eq!(3, optimized.iter().filter(|&c| *c == Command::Clear).count());
+        assert_eq!(run(commands), run(optimized));
+    }
+
+    #[test]
+    fn leading_loop_pattern() {
+        let commands = leading_loops(Program::compile("[][-[.]]+[-]", false));
+        assert_eq!(vec![Command::Inc, Command::JmpFwd(0), Command::Dec, Command::JmpBack(0)], commands);
+
+        // an unmatched '[' is left for Program::try_new to report
+        let commands = Program::compile("[]+[", false);
+        assert_eq!(vec![Command::Inc, Command::JmpFwd(0)], leading_loops(commands));
+        let commands = Program::compile("[[]", false);
+        assert_eq!(commands, leading_loops(commands.clone()));
+        let commands = Program::compile("][+]", false);
+        assert_eq!(commands, leading_loops(commands.clone()));
+    }
+
+    #[test]
+    fn leading_loop_same_output() {
+        let raw = r#"[]++++++++++[>>+>+>++++++[<<+<+++>>>-]<<<<-]"A*$";?@![#>>+<<]>[>>]<<<<[>++<[-]]>.>."#;
+        let commands = Program::compile(raw, false);
+        let optimized = leading_loops(commands.clone());
+        assert_eq!(commands.len() - 2, optimized.len());
+        assert_eq!(run(commands), run(optimized));
+
+        let raw = "[this program prints a > b + c.]>+++[->+++<]>.";
+        let commands = Program::compile(raw, false);
+        let optimized = leading_loops(commands.clone());
+        assert_eq!(optimized, Program::compile(">+++[->+++<]>.", false));
+        assert_eq!(run(commands), run(optimized));
+    }
+
+    #[test]
+    fn lower_to_ir() {
+        let ir = optimize(Program::compile("++[->+++<]>[-]>>,[.>]#", true));
+        let expected = vec![
+            OpIR::Add(2), OpIR::MulAdd(vec![(1, 3)]), OpIR::Move(1), OpIR::Clear, OpIR::Move(2), OpIR::In,
+            OpIR::Loop(vec![OpIR::Out, OpIR::Move(1)]),
+            OpIR::Debug,
+        ];
+        assert_eq!(expected, ir);
+
+        let listing: String = ir.iter().map(|op| op.to_string()).collect();
+        assert_eq!("add 2\nmuladd 1:3\nmove 1\nclear\nmove 2\nin\nloop\n  out\n  move 1\nend\ndebug\n", listing);
+    }
+
+    #[test]
+    fn mul_loop_pattern() {
+        let commands = mul_loops(coalesce(Program::compile("[->+>+++<<]>[>--<-]", false)));
+        let expected = vec![
+            Command::MulAdd(vec![(1, 1), (2, 3)]), Command::Move(1), Command::MulAdd(vec![(1, -2)]),
+        ];
+        assert_eq!(expected, commands);
+
+        // loops that move, step by more than one, do IO, or nest are left alone
+        for raw in ["[->+<<]", "[-->+<]", "[->.<]", "[->[>]<]", "[+>+<]"].iter() {
+            let commands = Program::compile(raw, false);
+            assert_eq!(commands, mul_loops(commands.clone()), "{}", raw);
+        }
+    }
+
+    #[test]
+    fn mul_loop_same_output() {
+        let programs = [
+            "+++++[->+++<]>.",
+            "++++[->++>+++<<]>.>.",
+            "+++[->>-<<]>>.",
+            "++[>+<-]>.",
+            "+++[>+++++[->++<]<-]>>.",
+            ">+++<+++++[>[->+<]<-]>>.",
+            "++++++++[->-[->+<]<]>>.",
+        ];
+        for raw in programs.iter() {
+            let commands = Program::compile(raw, false);
+            let optimized = all(commands.clone());
+            assert!(optimized.iter().any(|c| matches!(*c, Command::MulAdd(_))), "{}", raw);
+            assert_eq!(run(commands), run(optimized), "{}", raw);
+        }
+    }
+}
@@ -1,61 +1,2727 @@
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs::File;
 use std::io;
-use std::io::{Read, Write};
+use std::io::{BufRead, BufReader, BufWriter, IsTerminal, Read, Write};
+use std::mem;
+use std::path::Path;
+use std::slice;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use cell::Cell;
+use command::{command_for, Command};
+use command_map::CommandMap;
+use error::{BfError, BracketError, ParseError, UnmatchedBracket};
+use machine::{self, Machine};
+use optimize::Folded;
+use transpile;
+
+#[cfg(feature = "jit")]
+mod jit;
+
+// Position is where a command was found in the program source, offset is in
+// bytes while line and column count characters starting from 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+// number of cells on the tape unless a size is given
+pub const DEFAULT_TAPE_SIZE: usize = 30000;
+
+// EofMode selects what ',' stores in the current cell once input is exhausted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum EofMode {
+    Unchanged,
+    Zero,
+    AllOnes,
+}
+
+// OutputMode selects how '.' writes the current cell, either as the raw byte
+// or as its decimal value followed by a space
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum OutputMode {
+    #[default]
+    Bytes,
+    Decimal,
+}
+
+// TapeMode selects what happens when the pointer moves past the end of the tape,
+// a growable tape is extended with zeroed cells up to an optional maximum length
+// and a bidirectional tape also grows to the left of the starting cell, while
+// a wrapping tape keeps its size and moves the pointer around to the other end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TapeMode {
+    Fixed,
+    Growable { max: Option<usize> },
+    Bidirectional,
+    Wrapping,
+}
+
+// how many commands run between checks of the clock when a timeout is set,
+// and of the flag when a run can be cancelled
+const CHECK_INTERVAL: u64 = 1024;
+
+// how many cells dump_memory prints on each line
+const DUMP_ROW_LEN: usize = 16;
+
+// how many cells either side of the pointer tape_view shows
+const VIEW_RADIUS: usize = 4;
+
+// how many of the most executed commands profile_summary lists
+const PROFILE_TOP: usize = 20;
+
+// RunStats sums up what a program has done since it was created or last reset
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RunStats {
+    // index in memory of the current cell, as returned by pointer
+    pub final_ptr: usize,
+    // number of commands executed
+    pub steps: u64,
+    // number of times '.' wrote a cell
+    pub outputs_written: u64,
+    // index in memory of the furthest right cell the pointer has been on
+    pub max_ptr: usize,
+}
+
+// Limits bounds how much work a single run may do before it is stopped with an error
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub max_steps: Option<u64>,
+    pub timeout: Option<Duration>,
+    // how many times '.' may write a cell
+    pub max_output: Option<u64>,
+}
+
+// Observers holds optional sinks that are told about a run as it happens,
+// separately from the program's own output
+#[derive(Default)]
+pub struct Observers<'a> {
+    // trace receives a line for every command before it is executed
+    pub trace: Option<&'a mut dyn Write>,
+    // echo receives a copy of every byte of input as ',' reads it
+    pub echo: Option<&'a mut dyn Write>,
+    // tape receives a view of the cells around the pointer after every '.'
+    pub tape: Option<&'a mut dyn Write>,
+    // debug receives what '#' prints, without it that goes to stderr
+    pub debug: Option<&'a mut dyn Write>,
+    // outputs receives a line for every byte '.' writes, naming the '.' it
+    // came from by pc and by source position when that is known
+    pub outputs: Option<&'a mut dyn Write>,
+    // warnings receives a line whenever lenient mode keeps the pointer on the
+    // tape, without it the warning is dropped
+    pub warnings: Option<&'a mut dyn Write>,
+}
+
+// EchoInput copies everything read from input to echo when there is one
+struct EchoInput<'a, R: 'a> {
+    input: &'a mut R,
+    echo: Option<&'a mut dyn Write>,
+}
+
+impl<'a, R: Read> Read for EchoInput<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.input.read(buf)?;
+        if let Some(ref mut echo) = self.echo {
+            echo.write_all(&buf[..n])?;
+            echo.flush()?;
+        }
+        Ok(n)
+    }
+}
+
+// StepResult reports whether a program can keep running after a step, C is
+// the cell type of the program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepResult<C = u8> {
+    Continue,
+    Halted,
+    // the program stopped before executing the command at this pc
+    Breakpoint(usize),
+    // the program stopped after a command set a watched cell to its value
+    Watchpoint { cell: usize, value: C },
+}
+
+// LoopStats describes the loops in a program, loops the optimizer has turned
+// into other commands aren't counted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoopStats {
+    pub count: usize,
+    // deepest nesting of loops, 0 for a program without any
+    pub max_depth: usize,
+}
+
+// CostModel weighs each kind of command for estimate_cost, a command inside
+// loops nested n deep costs loop_multiplier to the power n times its weight
+// so a multiplier of 1 counts every loop as running once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CostModel {
+    // '<', '>' and Move
+    pub moves: u64,
+    // '+', '-' and Add
+    pub arithmetic: u64,
+    // '.' and ','
+    pub io: u64,
+    // '[' and ']'
+    pub jumps: u64,
+    pub clear: u64,
+    // MulAdd costs this much for each cell it adds to
+    pub mul_add: u64,
+    pub debug: u64,
+    pub loop_multiplier: u64,
+}
+
+impl Default for CostModel {
+    fn default() -> CostModel {
+        CostModel {
+            moves: 1,
+            arithmetic: 1,
+            io: 1,
+            jumps: 1,
+            clear: 1,
+            mul_add: 1,
+            debug: 0,
+            loop_multiplier: 1,
+        }
+    }
+}
+
+// MachineState is everything that changes as a program runs, taken by
+// snapshot and put back with restore
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MachineState<C = u8> {
+    pub pc: usize,
+    pub ptr: usize,
+    // index in memory of the cell the program started on
+    pub origin: usize,
+    pub memory: Vec<C>,
+    pub steps: u64,
+    pub outputs_written: u64,
+    pub max_ptr: usize,
+    pub input_seen: bool,
+}
+
+// Undo holds what executing one command may change so that step_back can put
+// it back, cells are indexed relative to the origin as the tape can grow to
+// the left partway through a command
 #[derive(Debug, PartialEq, Eq)]
-pub enum Command {
-    Right,
-    Left,
-    Inc,
-    Dec,
-    Out,
-    In,
-    JmpFwd,
-    JmpBack,
-    Debug,
+struct Undo<C> {
+    pc: usize,
+    ptr: usize,
+    origin: usize,
+    tape_len: usize,
+    cells: Vec<(isize, C)>,
+    outputs_written: u64,
+    max_ptr: usize,
+    input_seen: bool,
+}
+
+// Reach is where a cell some distance from the pointer is: on the tape, past
+// one end of a tape that grows to fit it, given as the index past the right
+// end or how many cells short the left end is, or off a tape that can't grow
+enum Reach {
+    At(usize),
+    GrowRight(usize),
+    GrowLeft(usize),
+    Off(BfError),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Program<C = u8> {
+    commands: Vec<Command>,
+    memory: Vec<C>,
+    tape_size: usize,
+    pc: usize,
+    ptr: usize,
+    // the cell the pointer starts on, the program is free to move left of it
+    start_ptr: usize,
+    // what the tape holds at the start instead of zeroes, kept for resets
+    initial_memory: Option<Vec<C>>,
+    eof_mode: EofMode,
+    output_mode: OutputMode,
+    tape_mode: TapeMode,
+    // index in memory of the cell the program started on, only moves when
+    // a bidirectional tape grows to the left
+    origin: usize,
+    breakpoints: HashSet<usize>,
+    watches: Vec<(usize, C)>,
+    // cells whose values are added to every line of a trace
+    traced_cells: Vec<usize>,
+    // set when the program is stopped at a breakpoint so resuming executes
+    // the command instead of stopping on it again
+    paused: bool,
+    // execution counts for each command, only kept once profiling is enabled
+    profile: Option<Vec<u64>>,
+    // counts for stats, kept until the program is reset
+    steps: u64,
+    outputs_written: u64,
+    max_ptr: usize,
+    // strict_input makes reading with no input given at all an error, the
+    // flag below records whether any byte has been read since the last reset
+    strict_input: bool,
+    input_seen: bool,
+    // checked_cells makes arithmetic that would wrap a cell an error
+    checked_cells: bool,
+    // lenient clamps the pointer to the ends of the tape instead of failing
+    lenient: bool,
+    // where each command came from in the source, only known when set_positions is used
+    positions: Option<Vec<Position>>,
+    // an undo entry for each command executed, only kept once history is enabled
+    history: Option<Vec<Undo<C>>>,
+}
+
+impl Program {
+    // compile will compile the given string as a brainfuck program
+    // if debug_pound is true then '#' will be treated as a debug command
+    pub fn compile(input: &str, debug_pound: bool) -> Vec<Command> {
+        Program::compile_bytes(input.as_bytes(), debug_pound)
+    }
+
+    // compile_bytes compiles a program that may not be valid UTF-8, the
+    // commands are all ASCII so every other byte is a comment
+    pub fn compile_bytes(input: &[u8], debug_pound: bool) -> Vec<Command> {
+        machine::compile(input, debug_pound)
+    }
+
+    // compile_reader compiles the program as it is read so only the commands
+    // and the reader's buffer are held in memory, not the whole source
+    pub fn compile_reader<R: BufRead>(reader: R, debug_pound: bool) -> io::Result<Vec<Command>> {
+        let mut commands = Vec::new();
+        Program::read_commands(reader, debug_pound, |command, _| commands.push(command))?;
+        Ok(commands)
+    }
+
+    // tokenize_reader is compile_reader keeping the position of each command
+    // like tokenize does, columns count the bytes that start a character
+    pub fn tokenize_reader<R: BufRead>(reader: R, debug_pound: bool) -> io::Result<Vec<(Command, Position)>> {
+        let mut tokens = Vec::new();
+        Program::read_commands(reader, debug_pound, |command, position| tokens.push((command, position)))?;
+        Ok(tokens)
+    }
+
+    // read_commands passes each command read from reader to found along with
+    // where it is in the source
+    fn read_commands<R: BufRead, F: FnMut(Command, Position)>(mut reader: R, debug_pound: bool, mut found: F) -> io::Result<()> {
+        let mut position = Position { offset: 0, line: 1, column: 1 };
+        loop {
+            let len = {
+                let buf = match reader.fill_buf() {
+                    Ok(buf) => buf,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                };
+                for &b in buf {
+                    if let Some(command) = command_for(b, debug_pound) {
+                        found(command, position);
+                    }
+                    if b == b'\n' {
+                        position.line += 1;
+                        position.column = 1;
+                    } else if b & 0xc0 != 0x80 {
+                        position.column += 1;
+                    }
+                    position.offset += 1;
+                }
+                buf.len()
+            };
+            if len == 0 {
+                return Ok(());
+            }
+            reader.consume(len);
+        }
+    }
+
+    // compile_with compiles a program written with the characters in map
+    // instead of the standard ones
+    pub fn compile_with(input: &str, map: &CommandMap) -> Vec<Command> {
+        Program::tokenize_with(input, map).into_iter().map(|(command, _)| command).collect()
+    }
+
+    // tokenize is compile keeping the position in input of each command
+    pub fn tokenize(input: &str, debug_pound: bool) -> Vec<(Command, Position)> {
+        Program::tokenize_with(input, &CommandMap::standard(debug_pound))
+    }
+
+    pub fn tokenize_with(input: &str, map: &CommandMap) -> Vec<(Command, Position)> {
+        Program::tokenize_checked(input, map, false).unwrap()
+    }
+
+    // tokenize_strict is tokenize_with where only whitespace and comment
+    // regions may appear between commands, anything else is an error
+    pub fn tokenize_strict(input: &str, map: &CommandMap) -> Result<Vec<(Command, Position)>, ParseError> {
+        Program::tokenize_checked(input, map, true)
+    }
+
+    fn tokenize_checked(input: &str, map: &CommandMap, strict: bool) -> Result<Vec<(Command, Position)>, ParseError> {
+        let (tokens, strays) = Program::scan(input, map);
+        match strays.into_iter().next() {
+            Some(stray) if strict => Err(stray),
+            _ => Ok(tokens),
+        }
+    }
+
+    // scan tokenizes input and also gives every character that is neither a
+    // command, whitespace nor in a comment region
+    pub(crate) fn scan(input: &str, map: &CommandMap) -> (Vec<(Command, Position)>, Vec<ParseError>) {
+        let mut tokens = Vec::new();
+        let mut strays = Vec::new();
+        let (mut line, mut column) = (1, 1);
+        // how many comment regions the current character is in
+        let mut depth = 0;
+        for (offset, c) in input.char_indices() {
+            let pos = Position { offset, line, column };
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+
+            if let Some((open, close)) = map.comment_region() {
+                if c == open {
+                    depth += 1;
+                } else if c == close && depth > 0 {
+                    depth -= 1;
+                    continue;
+                }
+                if depth > 0 {
+                    continue;
+                }
+            }
+            if let Some(command) = map.get(c) {
+                tokens.push((command.clone(), pos));
+            } else if !c.is_whitespace() {
+                strays.push(ParseError { found: c, position: pos });
+            }
+        }
+
+        (tokens, strays)
+    }
+
+    // new panics if the brackets in commands are unbalanced, use try_new
+    // when the program text is not trusted
+    pub fn new(commands: Vec<Command>) -> Program {
+        Self::with_tape_size(commands, DEFAULT_TAPE_SIZE)
+    }
+
+    // try_new gives an unmatched bracket as its index in commands, parsing
+    // the source instead gives where the bracket is in it
+    pub fn try_new(commands: Vec<Command>) -> Result<Program, BracketError> {
+        Self::linked(commands, DEFAULT_TAPE_SIZE)
+    }
+
+    // compile_file reads and compiles the program in the file at path, bytes
+    // that aren't valid UTF-8 are treated as comments
+    pub fn compile_file<P: AsRef<Path>>(path: P, debug_pound: bool) -> Result<Program, BfError> {
+        let commands = Program::compile_reader(BufReader::new(File::open(path)?), debug_pound)?;
+        Ok(Program::try_new(commands)?)
+    }
+
+    // from_str panics on unbalanced brackets, parse the input through the
+    // FromStr impl to get the error back instead
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(input: &str) -> Program {
+        input.parse().unwrap()
+    }
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct Program {
-    commands: Vec<Command>,
-    jmptable: HashMap<usize, usize>,
-    memory: Vec<u8>,
-}
+// a program is displayed as its canonical source, the commands with no
+// comments or whitespace
+impl<C> fmt::Display for Program<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for command in &self.commands {
+            command.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, C> IntoIterator for &'a Program<C> {
+    type Item = &'a Command;
+    type IntoIter = slice::Iter<'a, Command>;
+
+    fn into_iter(self) -> slice::Iter<'a, Command> {
+        self.commands.iter()
+    }
+}
+
+// parsing a program reports an unmatched bracket by where it is in the source
+impl<C: Cell> FromStr for Program<C> {
+    type Err = UnmatchedBracket;
+
+    fn from_str(input: &str) -> Result<Program<C>, UnmatchedBracket> {
+        let (commands, positions): (Vec<Command>, Vec<Position>) = Program::tokenize(input, false).into_iter().unzip();
+        Self::try_with_cells(commands).map_err(|error| UnmatchedBracket { error, position: positions[error.index()] })
+    }
+}
+
+impl<C: Cell> Program<C> {
+    // with_cells and try_with_cells are the equivalents of new and try_new
+    // for programs running on a tape of wider cells
+    pub fn with_cells(commands: Vec<Command>) -> Program<C> {
+        Self::try_with_cells(commands).unwrap()
+    }
+
+    pub fn try_with_cells(commands: Vec<Command>) -> Result<Program<C>, BracketError> {
+        Self::linked(commands, DEFAULT_TAPE_SIZE)
+    }
+
+    // with_tape_size panics with the error try_with_tape_size gives, for
+    // unbalanced brackets or a tape_size of 0
+    pub fn with_tape_size(commands: Vec<Command>, tape_size: usize) -> Program<C> {
+        Self::try_with_tape_size(commands, tape_size).unwrap()
+    }
+
+    pub fn try_with_tape_size(commands: Vec<Command>, tape_size: usize) -> Result<Program<C>, BfError> {
+        if tape_size == 0 {
+            return Err(BfError::EmptyTape);
+        }
+        Ok(Self::linked(commands, tape_size)?)
+    }
+
+    // linked links the brackets of commands and sets up the program on a tape
+    // of tape_size cells, which is more than 0
+    fn linked(mut commands: Vec<Command>, tape_size: usize) -> Result<Program<C>, BracketError> {
+        machine::link(&mut commands)?;
+
+        Ok(Program {
+            commands,
+            memory: vec![C::default(); tape_size],
+            tape_size,
+            pc: 0,
+            ptr: 0,
+            start_ptr: 0,
+            initial_memory: None,
+            eof_mode: EofMode::Unchanged,
+            output_mode: OutputMode::Bytes,
+            tape_mode: TapeMode::Fixed,
+            origin: 0,
+            breakpoints: HashSet::new(),
+            watches: Vec::new(),
+            traced_cells: Vec::new(),
+            paused: false,
+            profile: None,
+            steps: 0,
+            outputs_written: 0,
+            max_ptr: 0,
+            strict_input: false,
+            input_seen: false,
+            checked_cells: false,
+            lenient: false,
+            positions: None,
+            history: None,
+        })
+    }
+
+    pub fn set_eof_mode(&mut self, mode: EofMode) {
+        self.eof_mode = mode;
+    }
+
+    pub fn set_output_mode(&mut self, mode: OutputMode) {
+        self.output_mode = mode;
+    }
+
+    pub fn set_tape_mode(&mut self, mode: TapeMode) {
+        self.tape_mode = mode;
+    }
+
+    // set_start_ptr moves the pointer to the cell it starts on, for this run
+    // and every run after a reset. It fails if the cell is off the tape.
+    pub fn set_start_ptr(&mut self, ptr: usize) -> Result<(), BfError> {
+        if ptr >= self.tape_size {
+            return Err(BfError::StartOffTape { ptr, tape_size: self.tape_size });
+        }
+        self.start_ptr = ptr;
+        self.ptr = ptr;
+        self.max_ptr = ptr;
+        Ok(())
+    }
+
+    // set_initial_memory makes the tape start out as memory, for this run and
+    // every run after a reset, the tape size becomes the length of memory
+    pub fn set_initial_memory(&mut self, memory: Vec<C>) {
+        self.set_memory(memory.clone());
+        self.initial_memory = Some(memory);
+    }
+
+    // from_folded builds the rest of a program folded by optimize::fold_prefix
+    // so it starts from the tape and pointer the folded commands left
+    pub fn from_folded(folded: Folded<C>) -> Result<Program<C>, BfError> {
+        let mut prog = Program::try_with_tape_size(folded.commands, folded.memory.len())?;
+        prog.set_initial_memory(folded.memory);
+        prog.set_start_ptr(folded.ptr)?;
+        Ok(prog)
+    }
+
+    // set_strict_input makes ',' fail with NoInput when the input was empty
+    // from the start, once a byte has been read the end of input is handled
+    // by the eof mode as usual
+    pub fn set_strict_input(&mut self, strict: bool) {
+        self.strict_input = strict;
+    }
+
+    // set_positions gives the source position of each command, as returned by
+    // tokenize, so debug output can point into the source. It panics unless
+    // there is exactly one position per command.
+    pub fn set_positions(&mut self, positions: Vec<Position>) {
+        assert_eq!(positions.len(), self.commands.len(), "need one position per command");
+        self.positions = Some(positions);
+    }
+
+    // set_checked_cells makes a command that would take a cell past its
+    // largest or smallest value fail with CellOverflow instead of wrapping
+    pub fn set_checked_cells(&mut self, checked: bool) {
+        self.checked_cells = checked;
+    }
+
+    // set_lenient makes a move off either end of the tape leave the pointer on
+    // the first or last cell, with a line for the warnings observer, instead of
+    // failing with PointerUnderflow or PointerOverflow
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
+    // set_breakpoint makes step and run stop before executing the command at pc,
+    // running again carries on from the breakpoint
+    pub fn set_breakpoint(&mut self, pc: usize) {
+        self.breakpoints.insert(pc);
+    }
+
+    pub fn clear_breakpoint(&mut self, pc: usize) {
+        self.breakpoints.remove(&pc);
+    }
+
+    // enable_profiling starts counting how many times each command is executed
+    pub fn enable_profiling(&mut self) {
+        if self.profile.is_none() {
+            self.profile = Some(vec![0; self.commands.len()]);
+        }
+    }
+
+    // enable_history starts recording an undo entry for every command executed
+    // so step_back can rewind the program. The log grows with every step so
+    // this is meant for debugging short runs.
+    pub fn enable_history(&mut self) {
+        if self.history.is_none() {
+            self.history = Some(Vec::new());
+        }
+    }
+
+    // step_back undoes the last command executed, restoring the program
+    // counter, pointer and tape. Output already written and input already
+    // read can't be taken back. It returns false when history isn't enabled
+    // or there is nothing left to undo.
+    pub fn step_back(&mut self) -> bool {
+        let undo = match self.history.as_mut().and_then(|history| history.pop()) {
+            Some(undo) => undo,
+            None => return false,
+        };
+
+        // drop any cells the command grew the tape by
+        let grown_left = self.origin - undo.origin;
+        self.memory.drain(..grown_left);
+        self.memory.truncate(undo.tape_len);
+        self.origin = undo.origin;
+        for (cell, value) in undo.cells {
+            self.memory[(self.origin as isize + cell) as usize] = value;
+        }
+
+        self.pc = undo.pc;
+        self.ptr = undo.ptr;
+        self.paused = false;
+        self.steps -= 1;
+        self.outputs_written = undo.outputs_written;
+        self.max_ptr = undo.max_ptr;
+        self.input_seen = undo.input_seen;
+        // profiling may have been turned on after the command ran
+        if let Some(ref mut counts) = self.profile {
+            counts[self.pc] = counts[self.pc].saturating_sub(1);
+        }
+        true
+    }
+
+    // record saves an undo entry for the command about to be executed
+    fn record(&mut self) {
+        if self.history.is_none() {
+            return;
+        }
+
+        let cells = match self.commands[self.pc] {
+            Command::Inc | Command::Dec | Command::Add(_) | Command::Clear | Command::In => vec![self.ptr],
+            Command::MulAdd(ref terms) => {
+                let mut cells = vec![self.ptr];
+                cells.extend(terms.iter().filter_map(|&(offset, _)| self.written_cell(offset)));
+                cells
+            },
+            _ => Vec::new(),
+        };
+        let undo = Undo {
+            pc: self.pc,
+            ptr: self.ptr,
+            origin: self.origin,
+            tape_len: self.memory.len(),
+            cells: cells.into_iter().map(|cell| (cell as isize - self.origin as isize, self.memory[cell])).collect(),
+            outputs_written: self.outputs_written,
+            max_ptr: self.max_ptr,
+            input_seen: self.input_seen,
+        };
+        if let Some(ref mut history) = self.history {
+            history.push(undo);
+        }
+    }
+
+    // written_cell finds the cell a MulAdd term offset from the pointer writes
+    // to on the tape as it is, the same cell step resolves it to. It is None
+    // for a cell the tape would have to grow to reach.
+    fn written_cell(&self, offset: isize) -> Option<usize> {
+        match self.find_cell(offset) {
+            Reach::At(cell) => Some(cell),
+            Reach::Off(err) => self.clamped(&err),
+            Reach::GrowRight(_) | Reach::GrowLeft(_) => None,
+        }
+    }
+
+    // profile holds the execution count of each command, indexed like the commands
+    pub fn profile(&self) -> Option<&[u64]> {
+        self.profile.as_ref().map(|counts| &counts[..])
+    }
+
+    // profile_summary lists the most executed commands followed by the totals
+    // for each kind of command, hottest first
+    pub fn profile_summary(&self) -> String {
+        let counts = match self.profile {
+            Some(ref counts) => counts,
+            None => return String::new(),
+        };
+
+        let mut hottest: Vec<usize> = (0..counts.len()).filter(|&pc| counts[pc] > 0).collect();
+        hottest.sort_by(|&a, &b| counts[b].cmp(&counts[a]).then(a.cmp(&b)));
+        let mut summary = String::from("hottest commands:\n");
+        for &pc in hottest.iter().take(PROFILE_TOP) {
+            summary.push_str(&format!("{:>8} {:>12}  {:?}\n", pc, counts[pc], self.commands[pc]));
+        }
+
+        let mut kinds: Vec<(&str, u64)> = Vec::new();
+        for (command, &n) in self.commands.iter().zip(counts) {
+            let kind = command.kind();
+            match kinds.iter_mut().find(|&&mut (k, _)| k == kind) {
+                Some(entry) => entry.1 += n,
+                None => kinds.push((kind, n)),
+            }
+        }
+        kinds.retain(|&(_, n)| n > 0);
+        kinds.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        summary.push_str("by command:\n");
+        for (kind, n) in kinds {
+            summary.push_str(&format!("{:>8} {:>12}\n", kind, n));
+        }
+        summary
+    }
+
+    // break_when_cell makes step and run stop after any command that sets the cell
+    // at index cell in memory to value, the cell is only checked when it is written
+    pub fn break_when_cell(&mut self, cell: usize, value: C) {
+        self.watches.push((cell, value));
+    }
+
+    // watch adds the values of the given cells, indexed like memory, to every
+    // line of a trace, replacing any cells watched before. Cells past the end
+    // of the tape are left out.
+    pub fn watch(&mut self, cells: &[usize]) {
+        self.traced_cells = cells.to_vec();
+    }
+
+    pub fn run<R: Read, W: Write>(&mut self, input: &mut R, output: &mut W) -> Result<StepResult<C>, BfError> {
+        self.run_with_limits(input, output, &Limits::default())
+    }
+
+    // run_to_vec runs the program against a fixed input and collects everything it prints
+    pub fn run_to_vec(&mut self, input: &[u8]) -> Result<Vec<u8>, BfError> {
+        let mut output = Vec::new();
+        self.run_with_input_bytes(input, &mut output)?;
+        Ok(output)
+    }
+
+    // run_with_input_bytes runs the program with input read from a slice, once
+    // it is used up ',' follows the eof mode
+    pub fn run_with_input_bytes<W: Write>(&mut self, input: &[u8], output: &mut W) -> Result<StepResult<C>, BfError> {
+        let mut input = input;
+        self.run(&mut input, output)
+    }
+
+    // run_with_limit returns an error once max_steps commands have been executed
+    // without the program finishing
+    pub fn run_with_limit<R: Read, W: Write>(&mut self, input: &mut R, output: &mut W, max_steps: u64) -> Result<StepResult<C>, BfError> {
+        self.run_with_limits(input, output, &Limits { max_steps: Some(max_steps), ..Limits::default() })
+    }
+
+    // run_with_timeout returns an error once the program has run for longer than timeout,
+    // the clock is only checked every few commands so the run may overshoot slightly
+    pub fn run_with_timeout<R: Read, W: Write>(&mut self, input: &mut R, output: &mut W, timeout: Duration) -> Result<StepResult<C>, BfError> {
+        self.run_with_limits(input, output, &Limits { timeout: Some(timeout), ..Limits::default() })
+    }
+
+    pub fn run_with_limits<R: Read, W: Write>(&mut self, input: &mut R, output: &mut W, limits: &Limits) -> Result<StepResult<C>, BfError> {
+        self.run_with(input, output, limits, &mut Observers::default())
+    }
+
+    // run_traced writes a line to trace for every command executed giving the
+    // pc, pointer, current cell value and the command
+    pub fn run_traced<R: Read, W: Write, T: Write>(&mut self, input: &mut R, output: &mut W, trace: &mut T) -> Result<StepResult<C>, BfError> {
+        self.run_with(input, output, &Limits::default(), &mut Observers { trace: Some(trace), ..Observers::default() })
+    }
+
+    // run_cancellable runs the program until it finishes or cancel is set,
+    // which may be done from another thread. The flag is only checked every
+    // few commands and the run stops with a Cancelled error.
+    pub fn run_cancellable<R: Read, W: Write>(&mut self, input: &mut R, output: &mut W, cancel: &AtomicBool) -> Result<StepResult<C>, BfError> {
+        self.run_until_cancelled(input, output, &Limits::default(), &mut Observers::default(), Some(cancel))
+    }
+
+    // run_with buffers the output internally, the buffer is flushed before
+    // input is read, before debug output and when the run finishes
+    pub fn run_with<R: Read, W: Write>(&mut self, input: &mut R, output: &mut W, limits: &Limits, observers: &mut Observers) -> Result<StepResult<C>, BfError> {
+        self.run_until_cancelled(input, output, limits, observers, None)
+    }
+
+    fn run_until_cancelled<R: Read, W: Write>(&mut self, input: &mut R, output: &mut W, limits: &Limits, observers: &mut Observers,
+            cancel: Option<&AtomicBool>) -> Result<StepResult<C>, BfError> {
+        let mut output = BufWriter::new(output);
+        let mut input = EchoInput { input, echo: observers.echo.as_mut().map(|echo| &mut **echo as &mut dyn Write) };
+        let start = Instant::now();
+        let mut steps = 0;
+        let mut written = 0;
+        while self.pc < self.commands.len() {
+            if limits.max_steps == Some(steps) {
+                return Err(BfError::StepLimitExceeded { pc: self.pc, steps });
+            }
+            if let Some(timeout) = limits.timeout {
+                if steps % CHECK_INTERVAL == 0 && start.elapsed() >= timeout {
+                    return Err(BfError::Timeout { pc: self.pc, timeout });
+                }
+            }
+            if let Some(cancel) = cancel {
+                if steps % CHECK_INTERVAL == 0 && cancel.load(Ordering::Relaxed) {
+                    output.flush()?;
+                    return Err(BfError::Cancelled { pc: self.pc });
+                }
+            }
+            steps += 1;
+
+            if self.at_breakpoint() {
+                output.flush()?;
+                return Ok(StepResult::Breakpoint(self.pc));
+            }
+            if let Some(ref mut trace) = observers.trace {
+                write!(trace, "pc={} ptr={} cell={:?} {:?}", self.pc, self.ptr, self.memory[self.ptr], self.commands[self.pc])?;
+                for &cell in &self.traced_cells {
+                    if let Some(value) = self.memory.get(cell) {
+                        write!(trace, " [{}]={:?}", cell, value)?;
+                    }
+                }
+                writeln!(trace)?;
+            }
+            let is_out = self.commands[self.pc] == Command::Out;
+            if is_out {
+                if limits.max_output == Some(written) {
+                    output.flush()?;
+                    return Err(BfError::OutputLimitExceeded { pc: self.pc, limit: written });
+                }
+                written += 1;
+            }
+            let show_tape = observers.tape.is_some() && is_out;
+            let annotation = if observers.outputs.is_some() && is_out { Some(self.annotate_output()) } else { None };
+            // without a debug sink '#' writes straight to stderr, so anything
+            // the observers are holding in a buffer has to come out first
+            if observers.debug.is_none() && self.commands[self.pc] == Command::Debug {
+                for sink in [&mut observers.trace, &mut observers.tape, &mut observers.warnings].iter_mut() {
+                    if let Some(ref mut sink) = **sink {
+                        sink.flush()?;
+                    }
+                }
+            }
+            let result = self.execute(&mut input, &mut output, observers.debug.as_mut().map(|debug| &mut **debug as &mut dyn Write),
+                observers.warnings.as_mut().map(|warnings| &mut **warnings as &mut dyn Write)).map_err(|e| self.locate(e))?;
+            if let (true, Some(ref mut tape)) = (show_tape, observers.tape.as_mut()) {
+                tape.write_all(self.tape_view().as_bytes())?;
+            }
+            if let (Some(line), Some(ref mut outputs)) = (annotation, observers.outputs.as_mut()) {
+                outputs.write_all(line.as_bytes())?;
+            }
+            if let StepResult::Watchpoint { .. } = result {
+                output.flush()?;
+                return Ok(result);
+            }
+        }
+        output.flush()?;
+
+        Ok(StepResult::Halted)
+    }
+
+    // step executes the command at the program counter, a halted program
+    // is left untouched
+    pub fn step<R: Read, W: Write>(&mut self, input: &mut R, output: &mut W) -> Result<StepResult<C>, BfError> {
+        if self.pc >= self.commands.len() {
+            return Ok(StepResult::Halted);
+        }
+        if self.at_breakpoint() {
+            return Ok(StepResult::Breakpoint(self.pc));
+        }
+
+        self.execute(input, output, None, None).map_err(|e| self.locate(e))
+    }
+
+    // run_until_output runs the program until '.' writes target and returns
+    // Continue right after, so the caller can respond before resuming. It
+    // otherwise stops like step, at a breakpoint, watchpoint or the end.
+    pub fn run_until_output<R: Read, W: Write>(&mut self, input: &mut R, output: &mut W, target: u8) -> Result<StepResult<C>, BfError> {
+        loop {
+            let writes_target = self.pc < self.commands.len()
+                && self.commands[self.pc] == Command::Out
+                && self.memory[self.ptr].to_byte() == target;
+            match self.step(input, output)? {
+                StepResult::Continue if writes_target => return Ok(StepResult::Continue),
+                StepResult::Continue => {},
+                result => return Ok(result),
+            }
+        }
+    }
+
+    // annotate_output describes the byte the '.' at pc is about to write
+    fn annotate_output(&self) -> String {
+        let mut line = format!("pc={}", self.pc);
+        if let Some(pos) = self.positions.as_ref().and_then(|positions| positions.get(self.pc)) {
+            line.push_str(&format!(" line={} column={}", pos.line, pos.column));
+        }
+        let byte = self.memory[self.ptr].to_byte();
+        line.push_str(&format!(" byte={}", byte));
+        if byte.is_ascii_graphic() || byte == b' ' {
+            line.push_str(&format!(" {:?}", char::from(byte)));
+        }
+        line.push('\n');
+        line
+    }
+
+    // locate fills in the source position of the command at pc on the error
+    // it stopped with, when the positions are known
+    fn locate(&self, mut error: BfError) -> BfError {
+        let at = self.positions.as_ref().and_then(|positions| positions.get(self.pc)).cloned();
+        match error {
+            BfError::PointerUnderflow { ref mut position, .. } | BfError::PointerOverflow { ref mut position, .. }
+                | BfError::CellOverflow { ref mut position, .. } | BfError::NoInput { ref mut position, .. } => *position = at,
+            _ => {},
+        }
+        error
+    }
+
+    // at_breakpoint reports whether the program should stop before the current
+    // command, a breakpoint only stops the program once until it is resumed
+    fn at_breakpoint(&mut self) -> bool {
+        if self.paused || !self.breakpoints.contains(&self.pc) {
+            self.paused = false;
+            return false;
+        }
+        self.paused = true;
+        true
+    }
+
+    fn execute<R: Read, W: Write>(&mut self, input: &mut R, output: &mut W, debug: Option<&mut dyn Write>,
+            warnings: Option<&mut dyn Write>) -> Result<StepResult<C>, BfError> {
+        self.record();
+        if let Some(ref mut counts) = self.profile {
+            counts[self.pc] += 1;
+        }
+        self.steps += 1;
+
+        let pc = self.pc;
+        if self.commands[pc] == Command::Debug {
+            output.flush()?;
+            self.debug(self.ptr, pc, debug)?;
+            self.pc += 1;
+        } else {
+            // the commands are taken out while the step runs so it can borrow
+            // the rest of the program
+            let commands = mem::take(&mut self.commands);
+            let warnings = warnings.map(|warnings| warnings as &mut dyn Write);
+            let next = machine::step(&mut Running { prog: self, input, output, warnings }, &commands, pc);
+            self.commands = commands;
+            self.pc = next?;
+        }
+        let watch = if self.watches.is_empty() { None } else { self.triggered_watch(pc) };
+
+        if let Some((cell, value)) = watch {
+            Ok(StepResult::Watchpoint { cell, value })
+        } else if self.pc >= self.commands.len() {
+            Ok(StepResult::Halted)
+        } else {
+            Ok(StepResult::Continue)
+        }
+    }
+
+    // write_cell writes the current cell in the output mode
+    fn write_cell<W: Write + ?Sized>(&self, output: &mut W) -> io::Result<()> {
+        let cell = self.memory[self.ptr];
+        match self.output_mode {
+            OutputMode::Bytes => output.write_all(&[cell.to_byte()]),
+            OutputMode::Decimal => write!(output, "{} ", cell),
+        }
+    }
+
+    // add_checked adds delta to the current cell, failing instead of wrapping
+    fn add_checked(&mut self, delta: i32) -> Result<(), BfError> {
+        let ptr = self.ptr;
+        self.memory[ptr] = self.memory[ptr].checked_add_delta(delta).ok_or(BfError::CellOverflow { pc: self.pc, ptr, position: None })?;
+        Ok(())
+    }
+
+    // read_cell reads a byte of input into the current cell, what is stored at
+    // the end of input depends on the eof mode
+    fn read_cell<R: Read + ?Sized>(&mut self, input: &mut R) -> Result<(), BfError> {
+        let ptr = self.ptr;
+        match read_byte(input)? {
+            Some(byte) => {
+                self.memory[ptr] = C::from_byte(byte);
+                self.input_seen = true;
+            },
+            None if self.strict_input && !self.input_seen => return Err(BfError::NoInput { pc: self.pc, position: None }),
+            None => match self.eof_mode {
+                EofMode::Unchanged => {},
+                EofMode::Zero => self.memory[ptr] = C::default(),
+                EofMode::AllOnes => self.memory[ptr] = C::all_ones(),
+            },
+        }
+        Ok(())
+    }
+
+    // reset clears the tape and rewinds the program so it can be run again,
+    // the settings of the program are kept
+    pub fn reset(&mut self) {
+        self.memory.clear();
+        match self.initial_memory {
+            Some(ref initial) => self.memory.extend_from_slice(initial),
+            None => self.memory.resize(self.tape_size, C::default()),
+        }
+        self.pc = 0;
+        self.ptr = self.start_ptr;
+        self.origin = 0;
+        self.paused = false;
+        self.input_seen = false;
+        self.steps = 0;
+        self.outputs_written = 0;
+        self.max_ptr = self.start_ptr;
+        if let Some(ref mut counts) = self.profile {
+            counts.iter_mut().for_each(|n| *n = 0);
+        }
+        if let Some(ref mut history) = self.history {
+            history.clear();
+        }
+    }
+
+    // to_c translates the program into a standalone C program
+    pub fn to_c(&self) -> String {
+        transpile::to_c(&self.commands, self.tape_size, C::BITS, C::SIGNED, self.eof_mode, self.output_mode)
+    }
+
+    // to_wat translates the program into a WebAssembly text module
+    pub fn to_wat(&self) -> String {
+        transpile::to_wat(&self.commands, self.tape_size, C::BITS, C::SIGNED, self.eof_mode, self.output_mode)
+    }
+
+    // to_rust translates the program into a standalone Rust program
+    pub fn to_rust(&self) -> String {
+        transpile::to_rust(&self.commands, self.tape_size, C::BITS, C::SIGNED, self.eof_mode, self.output_mode)
+    }
+
+    // listing prints each command on its own line as its index, its kind and
+    // then any operands separated by spaces, brackets give their jump target
+    pub fn listing(&self) -> String {
+        let mut listing = String::new();
+        for (pc, command) in self.commands.iter().enumerate() {
+            listing.push_str(&format!("{} {}", pc, command.kind()));
+            match *command {
+                Command::JmpFwd(target) | Command::JmpBack(target) => listing.push_str(&format!(" {}", target)),
+                Command::Add(n) => listing.push_str(&format!(" {}", n)),
+                Command::Move(n) => listing.push_str(&format!(" {}", n)),
+                Command::MulAdd(ref terms) => {
+                    for &(offset, factor) in terms {
+                        listing.push_str(&format!(" {}:{}", offset, factor));
+                    }
+                },
+                _ => {},
+            }
+            listing.push('\n');
+        }
+        listing
+    }
+
+    // explain prints each command on its own line as its index, its source
+    // and a description of what it does
+    pub fn explain(&self) -> String {
+        let mut explanation = String::new();
+        for (pc, command) in self.commands.iter().enumerate() {
+            explanation.push_str(&format!("{} {}: {}\n", pc, command, command.describe()));
+        }
+        explanation
+    }
+
+    // commands iterates over the compiled commands with their jump targets filled in
+    pub fn commands(&self) -> slice::Iter<'_, Command> {
+        self.commands.iter()
+    }
+
+    // loop_stats counts the loops in the program and how deeply they nest
+    pub fn loop_stats(&self) -> LoopStats {
+        let mut stats = LoopStats::default();
+        let mut depth = 0;
+        for command in &self.commands {
+            match *command {
+                Command::JmpFwd(_) => {
+                    stats.count += 1;
+                    depth += 1;
+                    stats.max_depth = usize::max(stats.max_depth, depth);
+                },
+                Command::JmpBack(_) => depth -= 1,
+                _ => {},
+            }
+        }
+        stats
+    }
+
+    // matching_bracket gives the pc of the bracket that matches the one at pc,
+    // None when pc isn't a bracket
+    pub fn matching_bracket(&self, pc: usize) -> Option<usize> {
+        match self.commands.get(pc) {
+            Some(&Command::JmpFwd(target)) | Some(&Command::JmpBack(target)) => Some(target),
+            _ => None,
+        }
+    }
+
+    // reads_input reports whether the program has any ',' so the caller knows
+    // whether it needs input at all
+    pub fn reads_input(&self) -> bool {
+        self.commands.contains(&Command::In)
+    }
+
+    // input_command_count is the number of ',' in the program, not how many
+    // bytes it will read
+    pub fn input_command_count(&self) -> usize {
+        self.commands.iter().filter(|&command| *command == Command::In).count()
+    }
+
+    // estimate_cost adds up the weight of every command without running the
+    // program, it is only a rough guide for comparing versions of a program
+    pub fn estimate_cost(&self, weights: &CostModel) -> u64 {
+        let mut depth = 0;
+        let mut cost: u64 = 0;
+        for command in &self.commands {
+            if let Command::JmpBack(_) = *command {
+                depth -= 1;
+            }
+            let weight = match *command {
+                Command::Right | Command::Left | Command::Move(_) => weights.moves,
+                Command::Inc | Command::Dec | Command::Add(_) => weights.arithmetic,
+                Command::Out | Command::In => weights.io,
+                Command::JmpFwd(_) | Command::JmpBack(_) => weights.jumps,
+                Command::Clear => weights.clear,
+                Command::MulAdd(ref targets) => weights.mul_add.saturating_mul(targets.len() as u64),
+                Command::Debug => weights.debug,
+            };
+            cost = cost.saturating_add(weight.saturating_mul(weights.loop_multiplier.saturating_pow(depth)));
+            if let Command::JmpFwd(_) = *command {
+                depth += 1;
+            }
+        }
+        cost
+    }
+
+    // stats gives the final state and counts of the runs since the program
+    // was created or reset
+    pub fn stats(&self) -> RunStats {
+        RunStats {
+            final_ptr: self.ptr,
+            steps: self.steps,
+            outputs_written: self.outputs_written,
+            max_ptr: self.max_ptr,
+        }
+    }
+
+    // pc is the index of the next command to execute
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    // snapshot captures the execution state so the program can be put back
+    // to this point later, the commands and settings aren't included
+    pub fn snapshot(&self) -> MachineState<C> {
+        MachineState {
+            pc: self.pc,
+            ptr: self.ptr,
+            origin: self.origin,
+            memory: self.memory.clone(),
+            steps: self.steps,
+            outputs_written: self.outputs_written,
+            max_ptr: self.max_ptr,
+            input_seen: self.input_seen,
+        }
+    }
+
+    // restore puts the program back to a snapshot, any history recorded
+    // since is dropped. It panics if the pointer or origin is off the tape or
+    // the program counter is past the end of the program.
+    pub fn restore(&mut self, state: MachineState<C>) {
+        assert!(state.ptr < state.memory.len() && state.origin < state.memory.len(), "snapshot pointer is off the tape");
+        assert!(state.pc <= self.commands.len(), "snapshot program counter is past the end of the program");
+        self.pc = state.pc;
+        self.ptr = state.ptr;
+        self.origin = state.origin;
+        self.memory = state.memory;
+        self.steps = state.steps;
+        self.outputs_written = state.outputs_written;
+        self.max_ptr = state.max_ptr;
+        self.input_seen = state.input_seen;
+        self.paused = false;
+        if let Some(ref mut history) = self.history {
+            history.clear();
+        }
+    }
+
+    // pointer is the index of the current cell in memory
+    pub fn pointer(&self) -> usize {
+        self.ptr
+    }
+
+    // memory is the whole tape, on a bidirectional tape cells left of where
+    // the program started come first
+    pub fn memory(&self) -> &[C] {
+        &self.memory
+    }
+
+    // set_memory replaces the tape with memory and puts the pointer on its
+    // first cell, the tape size becomes the length of memory. A reset clears
+    // the tape back to zeros. It panics if memory is empty.
+    pub fn set_memory(&mut self, memory: Vec<C>) {
+        assert!(self.start_ptr < memory.len(), "start pointer must be on the tape");
+        self.tape_size = memory.len();
+        self.memory = memory;
+        self.ptr = self.start_ptr;
+        self.max_ptr = self.start_ptr;
+        self.origin = 0;
+    }
+
+    // triggered_watch finds a watch on a cell written by the command at pc,
+    // which was just executed
+    fn triggered_watch(&self, pc: usize) -> Option<(usize, C)> {
+        let ptr = self.ptr;
+        let written = |cell: usize| match self.commands[pc] {
+            Command::Inc | Command::Dec | Command::Add(_) | Command::Clear | Command::In => cell == ptr,
+            Command::MulAdd(ref terms) => {
+                cell == ptr || terms.iter().any(|&(offset, _)| self.written_cell(offset) == Some(cell))
+            },
+            _ => false,
+        };
+
+        self.watches.iter().cloned().find(|&(cell, value)| {
+            written(cell) && self.memory.get(cell) == Some(&value)
+        })
+    }
+
+    // tape_view shows the cells around the pointer on one line, starting with
+    // the index of the first cell shown and with the current cell in brackets
+    pub fn tape_view(&self) -> String {
+        let start = self.ptr.saturating_sub(VIEW_RADIUS);
+        let end = usize::min(self.ptr + VIEW_RADIUS + 1, self.memory.len());
+        let mut view = format!("{}:", start);
+        for (i, cell) in self.memory[start..end].iter().enumerate() {
+            if start + i == self.ptr {
+                view.push_str(&format!(" [{}]", cell));
+            } else {
+                view.push_str(&format!(" {}", cell));
+            }
+        }
+        view.push('\n');
+        view
+    }
+
+    // histogram counts how many cells on the tape hold each value
+    pub fn histogram(&self) -> BTreeMap<C, usize> {
+        let mut counts = BTreeMap::new();
+        for &cell in &self.memory {
+            *counts.entry(cell).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    // histogram_summary gives the number of zero and nonzero cells followed by
+    // the count of each nonzero value, lowest value first
+    pub fn histogram_summary(&self) -> String {
+        let counts = self.histogram();
+        let zero = counts.get(&C::default()).cloned().unwrap_or(0);
+        let mut summary = format!("{} zero cells, {} nonzero cells\n", zero, self.memory.len() - zero);
+        for (value, n) in counts.iter().filter(|&(value, _)| !value.is_zero()) {
+            summary.push_str(&format!("{:>12} {:>12}\n", value, n));
+        }
+        summary
+    }
+
+    // dump_memory formats len cells starting at start in rows like hexdump, with
+    // the offset, the cells in hex and then the cells that fit in a byte as ASCII
+    pub fn dump_memory(&self, start: usize, len: usize) -> String {
+        let start = usize::min(start, self.memory.len());
+        let end = usize::min(start.saturating_add(len), self.memory.len());
+        let width = (C::BITS / 4) as usize;
+
+        let mut dump = String::new();
+        for (row, cells) in self.memory[start..end].chunks(DUMP_ROW_LEN).enumerate() {
+            let hex: Vec<String> = cells.iter().map(|c| format!("{:0w$x}", c, w = width)).collect();
+            let ascii: String = cells.iter().map(|&c| match c.to_byte() {
+                b @ 0x20..=0x7e if C::from_byte(b) == c => char::from(b),
+                _ => '.',
+            }).collect();
+            dump.push_str(&format!("{:08x}  {:<w$}  |{}|\n", start + row * DUMP_ROW_LEN, hex.join(" "),
+                ascii, w = DUMP_ROW_LEN * (width + 1) - 1));
+        }
+        dump
+    }
+
+    // add_product adds value times factor to the cell offset from the pointer
+    // for a MulAdd, the pointer stays where it is
+    fn add_product(&mut self, offset: isize, value: C, factor: i32, warnings: Option<&mut dyn Write>) -> Result<(), BfError> {
+        let target = self.reach_clamped(offset, warnings)?;
+        let cell = self.memory[target];
+        self.memory[target] = if self.checked_cells {
+            cell.checked_add_product(value, factor).ok_or(BfError::CellOverflow { pc: self.pc, ptr: target, position: None })?
+        } else {
+            cell.add_product(value, factor)
+        };
+        self.max_ptr = usize::max(self.max_ptr, target);
+        Ok(())
+    }
+
+    // move_by moves the pointer and keeps track of the furthest cell reached
+    fn move_by(&mut self, delta: isize, warnings: Option<&mut dyn Write>) -> Result<(), BfError> {
+        self.ptr = self.reach_clamped(delta, warnings)?;
+        self.max_ptr = usize::max(self.max_ptr, self.ptr);
+        Ok(())
+    }
+
+    // reach_clamped is reach, but in lenient mode a cell off the tape is
+    // reported to warnings and the nearest end is given instead
+    fn reach_clamped(&mut self, delta: isize, warnings: Option<&mut dyn Write>) -> Result<usize, BfError> {
+        match self.reach(delta) {
+            Err(err) => match self.clamped(&err) {
+                Some(cell) => {
+                    warn_clamped(warnings, &err, cell)?;
+                    Ok(cell)
+                },
+                None => Err(err),
+            },
+            result => result,
+        }
+    }
+
+    // clamped is the end of the tape lenient mode keeps the pointer at
+    // instead of stopping with err
+    fn clamped(&self, err: &BfError) -> Option<usize> {
+        match *err {
+            BfError::PointerUnderflow { .. } if self.lenient => Some(0),
+            BfError::PointerOverflow { .. } if self.lenient => Some(self.memory.len() - 1),
+            _ => None,
+        }
+    }
+
+    // reach gives the index of the cell delta away from the pointer, growing
+    // the tape if the tape mode allows. Growing to the left shifts the pointer
+    // along with the rest of the tape so it stays on the same cell.
+    fn reach(&mut self, delta: isize) -> Result<usize, BfError> {
+        match self.find_cell(delta) {
+            Reach::At(cell) => Ok(cell),
+            Reach::GrowRight(cell) => {
+                self.memory.resize(cell + 1, C::default());
+                Ok(cell)
+            },
+            Reach::GrowLeft(short) => {
+                // at least double the tape to the left so repeated moves stay cheap
+                let extra = usize::max(self.memory.len(), short);
+                let mut memory = vec![C::default(); extra];
+                memory.append(&mut self.memory);
+                self.memory = memory;
+                self.origin += extra;
+                self.ptr += extra;
+                self.max_ptr += extra;
+                Ok(extra - short)
+            },
+            Reach::Off(err) => Err(err),
+        }
+    }
+
+    // find_cell works out where the cell delta away from the pointer is
+    // without changing the tape
+    fn find_cell(&self, delta: isize) -> Reach {
+        let len = self.memory.len();
+        if delta >= 0 {
+            let target = self.ptr + delta as usize;
+            if target < len {
+                return Reach::At(target);
+            }
+            match self.tape_mode {
+                TapeMode::Growable { max } if max.is_none_or(|max| target < max) => Reach::GrowRight(target),
+                TapeMode::Bidirectional => Reach::GrowRight(target),
+                TapeMode::Wrapping => Reach::At(target % len),
+                _ => Reach::Off(BfError::PointerOverflow { pc: self.pc, ptr: target, position: None }),
+            }
+        } else {
+            let back = delta.unsigned_abs();
+            if back <= self.ptr {
+                return Reach::At(self.ptr - back);
+            }
+            match self.tape_mode {
+                TapeMode::Wrapping => Reach::At((self.ptr + len - back % len) % len),
+                TapeMode::Bidirectional => Reach::GrowLeft(back - self.ptr),
+                _ => Reach::Off(BfError::PointerUnderflow { pc: self.pc, position: None }),
+            }
+        }
+    }
+
+    // write debug information to sink, or to stderr colored when it is a
+    // terminal if there isn't one
+    fn debug(&self, ptr: usize, pc: usize, sink: Option<&mut dyn Write>) -> io::Result<()> {
+        match sink {
+            Some(sink) => sink.write_all(self.debug_info(ptr, pc, false).as_bytes()),
+            None => {
+                let stderr = io::stderr();
+                let color = stderr.is_terminal();
+                stderr.lock().write_all(self.debug_info(ptr, pc, color).as_bytes())
+            },
+        }
+    }
+
+    // with color the current command and cell are highlighted and nonzero
+    // cells are in bold, otherwise the text is plain
+    fn debug_info(&self, ptr: usize, pc: usize, color: bool) -> String {
+        // clamp the context windows so a debug command near either end of
+        // the program or tape doesn't slice out of bounds
+        let com_len = self.commands.len();
+        let mem_len = self.memory.len();
+        let pc = usize::min(pc, com_len.saturating_sub(1));
+        let ptr = usize::min(ptr, mem_len.saturating_sub(1));
+        let pre_com = &self.commands[pc.saturating_sub(3)..pc];
+        let post_com = &self.commands[usize::min(pc+1, com_len)..usize::min(pc+3, com_len)];
+        let pre_mem = &self.memory[ptr.saturating_sub(3)..ptr];
+        let post_mem = &self.memory[usize::min(ptr+1, mem_len)..usize::min(ptr+3, mem_len)];
+
+        let mut info = String::from("--------------------------\n");
+        info.push_str(&format!("PC: {} | PTR: {}", pc, ptr));
+        if let Some(pos) = self.positions.as_ref().and_then(|positions| positions.get(pc)) {
+            info.push_str(&format!(" | LINE: {} | COLUMN: {}", pos.line, pos.column));
+        }
+        info.push('\n');
+        if color {
+            let cell = |cell: &C| if cell.is_zero() { format!("{:?}", cell) } else { format!("{}{:?}{}", BOLD, cell, RESET) };
+            info.push_str(&format!("COMS: {} -> {}{:?}{} <- {}\n",
+                color_list(pre_com, |c| format!("{:?}", c)), HIGHLIGHT, self.commands[pc], RESET, color_list(post_com, |c| format!("{:?}", c))));
+            info.push_str(&format!("MEM: {} -> {}{:?}{} <- {}\n",
+                color_list(pre_mem, cell), HIGHLIGHT, self.memory[ptr], RESET, color_list(post_mem, cell)));
+        } else {
+            info.push_str(&format!("COMS: {:?} -> {:?} <- {:?}\n", pre_com, self.commands[pc], post_com));
+            info.push_str(&format!("MEM: {:?} -> {:?} <- {:?}\n", pre_mem, self.memory[ptr], post_mem));
+        }
+        info.push_str("--------------------------\n");
+        info
+    }
+}
+
+// Running is a program partway through a step along with the input and output
+// of its run, it applies the settings of the program to each command
+struct Running<'a, C: 'a, R: 'a + ?Sized, W: 'a + ?Sized> {
+    prog: &'a mut Program<C>,
+    input: &'a mut R,
+    output: &'a mut W,
+    warnings: Option<&'a mut dyn Write>,
+}
+
+
+impl<'a, C: Cell, R: Read + ?Sized, W: Write + ?Sized> Machine for Running<'a, C, R, W> {
+    type Cell = C;
+    type Error = BfError;
+
+    fn current(&mut self) -> &mut C {
+        let ptr = self.prog.ptr;
+        &mut self.prog.memory[ptr]
+    }
+
+    fn move_by(&mut self, delta: isize, _pc: usize) -> Result<(), BfError> {
+        self.prog.move_by(delta, self.warnings.as_mut().map(|warnings| &mut **warnings as &mut dyn Write))
+    }
+
+    fn add(&mut self, delta: i32, _pc: usize) -> Result<(), BfError> {
+        if self.prog.checked_cells {
+            return self.prog.add_checked(delta);
+        }
+        let cell = self.current();
+        *cell = cell.add_delta(delta);
+        Ok(())
+    }
+
+    fn add_product(&mut self, offset: isize, value: C, factor: i32, _pc: usize) -> Result<(), BfError> {
+        self.prog.add_product(offset, value, factor, self.warnings.as_mut().map(|warnings| &mut **warnings as &mut dyn Write))
+    }
+
+    fn output(&mut self, _pc: usize) -> Result<(), BfError> {
+        self.prog.write_cell(self.output)?;
+        self.prog.outputs_written += 1;
+        Ok(())
+    }
+
+    fn input(&mut self, _pc: usize) -> Result<(), BfError> {
+        // anything printed so far may be a prompt for this input
+        self.output.flush()?;
+        self.prog.read_cell(self.input)
+    }
+}
+
+// warn_clamped tells warnings, when there is a sink for them, that lenient
+// mode kept the pointer on cell instead of failing with err
+fn warn_clamped(warnings: Option<&mut dyn Write>, err: &BfError, cell: usize) -> io::Result<()> {
+    match warnings {
+        Some(warnings) => writeln!(warnings, "warning: {}, keeping the pointer at cell {}", err, cell),
+        None => Ok(()),
+    }
+}
+
+// escape codes used for colored debug output
+const HIGHLIGHT: &str = "\x1b[7m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+// color_list formats items like a debug printed slice with each item formatted by show
+fn color_list<T, F: Fn(&T) -> String>(items: &[T], show: F) -> String {
+    format!("[{}]", items.iter().map(show).collect::<Vec<String>>().join(", "))
+}
+
+// SavedProgram is what a Program is serialized as, only the commands and
+// settings are kept and the jump table and tape are rebuilt on load
+#[cfg(feature = "serde")]
+#[derive(Serialize)]
+struct SavedProgramRef<'a> {
+    commands: &'a [Command],
+    tape_size: usize,
+    eof_mode: EofMode,
+    output_mode: OutputMode,
+    tape_mode: TapeMode,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct SavedProgram {
+    commands: Vec<Command>,
+    tape_size: usize,
+    eof_mode: EofMode,
+    // missing from programs saved before the output mode could be changed
+    #[serde(default)]
+    output_mode: OutputMode,
+    tape_mode: TapeMode,
+}
+
+#[cfg(feature = "serde")]
+impl<C: Cell> Serialize for Program<C> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SavedProgramRef {
+            commands: &self.commands,
+            tape_size: self.tape_size,
+            eof_mode: self.eof_mode,
+            output_mode: self.output_mode,
+            tape_mode: self.tape_mode,
+        }.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, C: Cell> Deserialize<'de> for Program<C> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let saved = SavedProgram::deserialize(deserializer)?;
+        if saved.tape_size == 0 {
+            return Err(de::Error::custom("tape must have at least one cell"));
+        }
+        let mut prog = Program::try_with_tape_size(saved.commands, saved.tape_size).map_err(de::Error::custom)?;
+        prog.set_eof_mode(saved.eof_mode);
+        prog.set_output_mode(saved.output_mode);
+        prog.set_tape_mode(saved.tape_mode);
+        Ok(prog)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<C: Cell> Program<C> {
+    // save writes the compiled program to a file as JSON
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), BfError> {
+        let mut file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut file, self).map_err(io::Error::from)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    // load reads a program written by save, it starts from a fresh tape
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Program<C>, BfError> {
+        let file = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(file).map_err(io::Error::from)?)
+    }
+}
+
+// read a single byte from input, returning None on EOF
+pub(crate) fn read_byte<R: Read + ?Sized>(input: &mut R) -> io::Result<Option<u8>> {
+    let mut buf = [0];
+    loop {
+        match input.read(&mut buf) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(buf[0])),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use optimize;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::error::Error;
+    use std::io::{empty, Cursor};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn hello_world() {
+        let raw = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+
+        let mut output = Vec::new();
+        let mut compiled = Program::from_str(raw);
+        compiled.run(&mut empty(), &mut output).unwrap();
+        let out: String = output.iter().map(|&b| char::from(b)).collect();
+        assert_eq!(out, "Hello World!\n");
+    }
+
+    #[test]
+    fn hello_world_comments() {
+        let raw = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+        let commented = r" 1 +++++ +++               Set Cell #0 to 8
+ 2 [
+ 3     >++++               Add 4 to Cell #1; this will always set Cell #1 to 4
+ 4     [                   as the cell will be cleared by the loop
+ 5         >++             Add 4*2 to Cell #2
+ 6         >+++            Add 4*3 to Cell #3
+ 7         >+++            Add 4*3 to Cell #4
+ 8         >+              Add 4 to Cell #5
+ 9         <<<<-           Decrement the loop counter in Cell #1
+10     ]                   Loop till Cell #1 is zero
+11     >+                  Add 1 to Cell #2
+12     >+                  Add 1 to Cell #3
+13     >-                  Subtract 1 from Cell #4
+14     >>+                 Add 1 to Cell #6
+15     [<]                 Move back to the first zero cell you find; this will
+16                         be Cell #1 which was cleared by the previous loop
+17     <-                  Decrement the loop Counter in Cell #0
+18 ]                       Loop till Cell #0 is zero
+19 
+20 The result of this is:
+21 Cell No :   0   1   2   3   4   5   6
+22 Contents:   0   0  72 104  88  32   8
+23 Pointer :   ^
+24 
+25 >>.                     Cell #2 has value 72 which is 'H'
+26 >---.                   Subtract 3 from Cell #3 to get 101 which is 'e'
+27 +++++ ++..+++.          Likewise for 'llo' from Cell #3
+28 >>.                     Cell #5 is 32 for the space
+29 <-.                     Subtract 1 from Cell #4 for 87 to give a 'W'
+30 <.                      Cell #3 was set to 'o' from the end of 'Hello'
+31 +++.----- -.----- ---.  Cell #3 for 'rl' and 'd'
+32 >>+.                    Add 1 to Cell #5 gives us an exclamation point
+33 >++.                    And finally a newline from Cell #6";
+
+        let mut raw_compiled = Program::from_str(raw);
+        let mut commented_compiled = Program::from_str(commented);
+        assert_eq!(raw_compiled, commented_compiled);
+
+        let mut raw_output = Vec::new();
+        let mut commented_output = Vec::new();
+
+        raw_compiled.run(&mut empty(), &mut raw_output).unwrap();
+        commented_compiled.run(&mut empty(), &mut commented_output).unwrap();
+
+        let raw_out: String = raw_output.iter().map(|&b| char::from(b)).collect();
+        let commented_out: String = commented_output.iter().map(|&b| char::from(b)).collect();
+        assert_eq!(raw_out, commented_out);
+        assert_eq!(raw_compiled, commented_compiled);
+
+        // the canonical source drops the comments
+        assert_eq!(commented_compiled.to_string(), raw);
+    }
+
+    // taken from http://www.hevanet.com/cristofd/brainfuck/tests.b
+    // "Goes to cell 30000 and reports from there with a '#'"
+    const ARRAY_SIZE: &str = "++++[>++++++<-]>[>+++++>+++++++<<-]>>++++<[[>[[>>+<<-]<]>>>-]>-[>+>+<<-]>]
++++++[>+++++++<<++>-]>.<<.";
+
+    #[test]
+    fn array_size_test() {
+        let mut prog = Program::from_str(ARRAY_SIZE);
+
+        let mut output = Vec::new();
+        prog.run(&mut empty(), &mut output).unwrap();
+
+        assert_eq!(b'#', output[0]);
+    }
+
+    #[test]
+    fn explain() {
+        let prog = Program::new(optimize::all(Program::compile(",>>><<<<<[->++>+<<]---", false)));
+        assert_eq!(prog.explain(), "0 ,: read a byte of input into the current cell\n\
+            1 <<: move the pointer 2 cells left\n\
+            2 [->++>+<<]: add 2 times the current cell to the cell 1 away, add 1 times the current cell to the cell 2 away, then set the current cell to 0\n\
+            3 ---: subtract 3 from the current cell\n");
+    }
+
+    #[test]
+    fn initial_memory() {
+        let mut prog = Program::from_str(".>.>+.");
+        prog.set_memory(vec![72, 105, 32]);
+        assert_eq!(prog.run_to_vec(&[]).unwrap(), b"Hi!");
+
+        prog.reset();
+        assert_eq!(prog.memory(), &[0, 0, 0]);
+    }
+
+    #[test]
+    fn loop_stats() {
+        assert_eq!(Program::from_str("+.").loop_stats(), LoopStats { count: 0, max_depth: 0 });
+        let prog = Program::from_str("+[>[-]<-]>[[>[+]]<[.]]");
+        assert_eq!(prog.loop_stats(), LoopStats { count: 6, max_depth: 3 });
+
+        // cleared loops are no longer loops
+        let prog = Program::new(optimize::all(Program::compile("+[>[-]<-]", false)));
+        assert_eq!(prog.loop_stats(), LoopStats { count: 1, max_depth: 1 });
+    }
+
+    #[test]
+    fn max_ptr() {
+        // the array size program reports from cell 30000, the last one
+        let mut prog = Program::from_str(ARRAY_SIZE);
+        prog.run_to_vec(&[]).unwrap();
+        assert_eq!(prog.stats().max_ptr, 29999);
+
+        // cells a multiply loop adds to count as reached
+        let mut prog = Program::new(optimize::all(Program::compile(">+[->>>+<<<]<", false)));
+        prog.run_to_vec(&[]).unwrap();
+        assert_eq!(prog.stats().max_ptr, 4);
+        assert_eq!(prog.stats().final_ptr, 0);
+    }
+
+    #[test]
+    fn stats() {
+        let mut prog = Program::from_str("++[>+++<-]>.>.<");
+        assert_eq!(prog.stats(), RunStats::default());
+        prog.run_to_vec(&[]).unwrap();
+        assert_eq!(prog.stats(), RunStats { final_ptr: 1, steps: 22, outputs_written: 2, max_ptr: 2 });
+
+        // single steps count too and reset starts over
+        prog.reset();
+        prog.step(&mut empty(), &mut Vec::new()).unwrap();
+        assert_eq!(prog.stats().steps, 1);
+        prog.reset();
+        assert_eq!(prog.stats(), RunStats::default());
+    }
+
+    #[test]
+    fn obscure_tests() {
+        // taken from http://www.hevanet.com/cristofd/brainfuck/tests.b
+        // "Tests for several obscure problems. Should output an H."
+        let raw = r#"[]++++++++++[>>+>+>++++++[<<+<+++>>>-]<<<<-]
+"A*$";?@![#>>+<<]>[>>]<<<<[>++<[-]]>.>."#;
+        let mut prog = Program::from_str(raw);
+
+        let mut output = Vec::new();
+        prog.run(&mut empty(), &mut output).unwrap();
+
+        assert_eq!(b'H', output[0]);
+    }
+
+    #[test]
+    fn debug() {
+        // test that programs are compiled correctly concerning the debug flag
+        let raw_simple = "#+.";
+        let comp_simple_debug = Program::compile(raw_simple, true);
+        let comp_simple_no_debug = Program::compile(raw_simple, false);
+        let expected_simple_debug = vec![Command::Debug, Command::Inc, Command::Out];
+        let expected_simple_no_debug = vec![Command::Inc, Command::Out];
+        
+        assert_eq!(expected_simple_debug, comp_simple_debug);
+        assert_eq!(expected_simple_no_debug, comp_simple_no_debug);
+
+        // test that debug printing handles edge of array cases without crashing
+        let raw = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.#";
+        let mut prog = Program::new(Program::compile(raw, true));
+        prog.debug(0, 0, None).unwrap();
+        prog.debug(30000-1, 0, None).unwrap();
+        
+        prog.run(&mut empty(), &mut Vec::new()).unwrap();
+    }
+
+    #[test]
+    fn debug_sink() {
+        let mut prog = Program::new(Program::compile("+#.", true));
+        let mut output = Vec::new();
+        let mut debug = Vec::new();
+        let mut observers = Observers { debug: Some(&mut debug), ..Observers::default() };
+        prog.run_with(&mut empty(), &mut output, &Limits::default(), &mut observers).unwrap();
+
+        assert_eq!(output, vec![1]);
+        assert_eq!(String::from_utf8(debug).unwrap(), prog.debug_info(0, 1, false));
+    }
+
+    #[test]
+    fn debug_at_start() {
+        // a debug command as the very first command, with the pointer at 0
+        let mut prog = Program::new(Program::compile("#+", true));
+        prog.run(&mut empty(), &mut Vec::new()).unwrap();
+        prog.debug(0, 0, None).unwrap();
+        prog.debug(1, 2, None).unwrap();
+
+        // and on a tape shorter than the context window
+        let mut small: Program = Program::with_tape_size(Program::compile(">#<#", true), 2);
+        small.run(&mut empty(), &mut Vec::new()).unwrap();
+    }
+
+    #[test]
+    fn debug_color() {
+        let mut prog = Program::from_str("++>+>");
+        prog.run(&mut empty(), &mut Vec::new()).unwrap();
+        prog.pc = 2;
+
+        // the plain format doesn't change
+        assert_eq!(prog.debug_info(1, 2, false), "--------------------------\n\
+            PC: 2 | PTR: 1\n\
+            COMS: [Inc, Inc] -> Right <- [Inc, Right]\n\
+            MEM: [2] -> 1 <- [0, 0]\n\
+            --------------------------\n");
+        assert_eq!(prog.debug_info(1, 2, true), "--------------------------\n\
+            PC: 2 | PTR: 1\n\
+            COMS: [Inc, Inc] -> \x1b[7mRight\x1b[0m <- [Inc, Right]\n\
+            MEM: [\x1b[1m2\x1b[0m] -> \x1b[7m1\x1b[0m <- [0, 0]\n\
+            --------------------------\n");
+    }
+
+    #[test]
+    fn debug_positions() {
+        let raw = "++\n>+ #\n";
+        let tokens = Program::tokenize(raw, true);
+        let mut prog = Program::new(tokens.iter().map(|t| t.0.clone()).collect());
+        assert!(prog.debug_info(1, 4, false).starts_with("--------------------------\nPC: 4 | PTR: 1\n"));
+
+        prog.set_positions(tokens.into_iter().map(|t| t.1).collect());
+        let info = prog.debug_info(1, 4, false);
+        assert!(info.starts_with("--------------------------\nPC: 4 | PTR: 1 | LINE: 2 | COLUMN: 4\n"), "{}", info);
+    }
+
+    #[test]
+    fn pointer_underflow() {
+        let mut prog = Program::from_str("+><<");
+
+        let err = prog.run(&mut empty(), &mut Vec::new()).unwrap_err();
+        assert_eq!(err.to_string(), "tape underflow at pc 3");
+    }
+
+    #[test]
+    fn error_position() {
+        let tokens = Program::tokenize("+>\n  <<.", false);
+        let mut prog = Program::new(tokens.iter().map(|t| t.0.clone()).collect());
+        prog.set_positions(tokens.into_iter().map(|t| t.1).collect());
+
+        let err = prog.run(&mut empty(), &mut Vec::new()).unwrap_err();
+        assert_eq!(err.to_string(), "tape underflow at pc 3 (line 2, column 4)");
+        assert_eq!(err.position(), Some(Position { offset: 6, line: 2, column: 4 }));
+        match err {
+            BfError::PointerUnderflow { pc: 3, .. } => {},
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn pointer_overflow() {
+        let raw: String = ">".repeat(30000);
+        let mut prog = Program::from_str(&raw);
+
+        let err = prog.run(&mut empty(), &mut Vec::new()).unwrap_err();
+        assert_eq!(err.to_string(), "tape overflow at pc 29999: pointer 30000");
+    }
+
+    #[test]
+    fn unbalanced_brackets() {
+        let unmatched_close = Program::try_new(Program::compile("+[-]]", false));
+        assert_eq!(unmatched_close.unwrap_err(), BracketError::UnmatchedClose(4));
+
+        let unmatched_open = Program::try_new(Program::compile("+[[-]", false));
+        assert_eq!(unmatched_open.unwrap_err(), BracketError::UnmatchedOpen(1));
+
+        assert!(Program::try_new(Program::compile("[[]][]", false)).is_ok());
+    }
+
+    #[test]
+    fn eof_modes() {
+        let cases = [(EofMode::Unchanged, 1), (EofMode::Zero, 0), (EofMode::AllOnes, 255)];
+        for &(mode, expected) in cases.iter() {
+            let mut prog = Program::from_str("+,");
+            prog.set_eof_mode(mode);
+            prog.run(&mut empty(), &mut Vec::new()).unwrap();
+            assert_eq!(expected, prog.memory[0], "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn output_modes() {
+        let mut prog = Program::from_str("++++++++[>+++++++++<-]>.<+++[>+++++++++++<-]>.");
+        assert_eq!(prog.run_to_vec(&[]).unwrap(), b"Hi");
+
+        prog.reset();
+        prog.set_output_mode(OutputMode::Decimal);
+        assert_eq!(prog.run_to_vec(&[]).unwrap(), b"72 105 ");
+
+        let mut signed: Program<i8> = Program::with_cells(Program::compile("-.", false));
+        signed.set_output_mode(OutputMode::Decimal);
+        assert_eq!(signed.run_to_vec(&[]).unwrap(), b"-1 ");
+    }
+
+    #[test]
+    fn strict_input() {
+        let mut prog = Program::from_str(",.");
+        prog.set_strict_input(true);
+        match prog.run_to_vec(&[]) {
+            Err(BfError::NoInput { pc: 0, .. }) => {},
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        // running out after some input follows the eof mode
+        let mut prog = Program::from_str(",.,.");
+        prog.set_strict_input(true);
+        prog.set_eof_mode(EofMode::Zero);
+        assert_eq!(prog.run_to_vec(b"a").unwrap(), b"a\0");
+        prog.reset();
+        assert!(prog.run_to_vec(&[]).is_err());
+
+        let mut lenient = Program::from_str(",.");
+        assert_eq!(lenient.run_to_vec(&[]).unwrap(), b"\0");
+    }
+
+    #[test]
+    fn checked_cells() {
+        let mut prog = Program::from_str("+>-");
+        assert!(prog.run_to_vec(&[]).is_ok());
+        prog.reset();
+        prog.set_checked_cells(true);
+        match prog.run_to_vec(&[]) {
+            Err(BfError::CellOverflow { pc: 2, ptr: 1, .. }) => {},
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        let raw = "++++++++++++++++[>++++++++++++++++<-]";
+        assert!(Program::from_str(raw).run_to_vec(&[]).is_ok());
+        for commands in [Program::compile(raw, false), optimize::all(Program::compile(raw, false))].iter() {
+            let mut prog = Program::new(commands.clone());
+            prog.set_checked_cells(true);
+            match prog.run_to_vec(&[]) {
+                Err(BfError::CellOverflow { ptr: 1, .. }) => {},
+                res => panic!("unexpected result {:?} for {:?}", res, commands),
+            }
+        }
+
+        // the limits follow the cell type
+        let mut wide: Program<u16> = Program::with_cells(Program::compile(raw, false));
+        wide.set_checked_cells(true);
+        assert!(wide.run_to_vec(&[]).is_ok());
+        let mut signed: Program<i8> = Program::with_cells(Program::compile("-", false));
+        signed.set_checked_cells(true);
+        assert!(signed.run_to_vec(&[]).is_ok());
+    }
+
+    #[test]
+    fn cell_wrapping() {
+        let mut prog = Program::from_str(&"+".repeat(256));
+        prog.run(&mut empty(), &mut Vec::new()).unwrap();
+        assert_eq!(0, prog.memory[0]);
+
+        let mut prog = Program::from_str("-");
+        prog.run(&mut empty(), &mut Vec::new()).unwrap();
+        assert_eq!(255, prog.memory[0]);
+    }
+
+    #[test]
+    fn cell_widths() {
+        // 10 * 30 = 300
+        let raw = "++++++++++[>++++++++++++++++++++++++++++++<-]";
+
+        let mut narrow = Program::from_str(raw);
+        narrow.run(&mut empty(), &mut Vec::new()).unwrap();
+        assert_eq!(44, narrow.memory[1]);
+
+        let mut wide = Program::<u16>::with_cells(Program::compile(raw, false));
+        wide.run(&mut empty(), &mut Vec::new()).unwrap();
+        assert_eq!(300, wide.memory[1]);
+
+        let mut wider = Program::<u32>::with_cells(Program::compile("-", false));
+        wider.run(&mut empty(), &mut Vec::new()).unwrap();
+        assert_eq!(u32::MAX, wider.memory[0]);
+    }
+
+    #[test]
+    fn signed_cells() {
+        let mut prog = Program::<i8>::with_cells(Program::compile("->-.<.", false));
+        let output = prog.run_to_vec(&[]).unwrap();
+        assert_eq!(prog.memory()[0], -1);
+        assert_eq!(prog.memory()[1], -1);
+        // '.' writes the two's complement byte
+        assert_eq!(output, vec![0xff, 0xff]);
+
+        // wrapping happens at the signed limits
+        let mut prog = Program::<i8>::with_cells(Program::compile(&"+".repeat(128), false));
+        prog.run(&mut empty(), &mut Vec::new()).unwrap();
+        assert_eq!(prog.memory()[0], i8::MIN);
+
+        let mut prog = Program::<i16>::with_cells(Program::compile(",>,", false));
+        prog.set_eof_mode(EofMode::AllOnes);
+        prog.run(&mut &[200][..], &mut Vec::new()).unwrap();
+        assert_eq!(&prog.memory()[..2], &[200, -1]);
+    }
+
+    #[test]
+    fn tape_size() {
+        let mut prog: Program = Program::with_tape_size(Program::compile(">>>+", false), 4);
+        prog.run(&mut empty(), &mut Vec::new()).unwrap();
+        assert_eq!(vec![0, 0, 0, 1], prog.memory);
+
+        let mut prog: Program = Program::with_tape_size(Program::compile(">>>>", false), 4);
+        let err = prog.run(&mut empty(), &mut Vec::new()).unwrap_err();
+        assert_eq!(err.to_string(), "tape overflow at pc 3: pointer 4");
+        assert_eq!(4, prog.memory.len());
+        match Program::<u8>::try_with_tape_size(Program::compile("+", false), 0) {
+            Err(BfError::EmptyTape) => {},
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn growable_tape() {
+        let raw = ">".repeat(50000) + "+-.";
+
+        let mut prog = Program::from_str(&raw);
+        prog.set_tape_mode(TapeMode::Growable { max: None });
+        let mut output = Vec::new();
+        prog.run(&mut empty(), &mut output).unwrap();
+        assert_eq!(vec![0], output);
+        assert_eq!(50001, prog.memory.len());
+
+        let mut prog = Program::from_str(&raw);
+        prog.set_tape_mode(TapeMode::Growable { max: Some(40000) });
+        let err = prog.run(&mut empty(), &mut Vec::new()).unwrap_err();
+        assert_eq!(err.to_string(), "tape overflow at pc 39999: pointer 40000");
+    }
+
+    #[test]
+    fn bidirectional_tape() {
+        let mut prog: Program = Program::with_tape_size(Program::compile("<<<+++<-.>.>>>.", false), 2);
+        prog.set_tape_mode(TapeMode::Bidirectional);
+        let mut output = Vec::new();
+        prog.run(&mut empty(), &mut output).unwrap();
+
+        assert_eq!(vec![255, 3, 0], output);
+        assert_eq!(3, prog.memory[prog.origin - 3]);
+        assert_eq!(255, prog.memory[prog.origin - 4]);
+
+        let mut prog = Program::from_str("<");
+        assert!(prog.run(&mut empty(), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn wrapping_tape() {
+        let mut prog: Program = Program::with_tape_size(Program::compile("<+<++>>>+++", false), 4);
+        prog.set_tape_mode(TapeMode::Wrapping);
+        prog.run(&mut empty(), &mut Vec::new()).unwrap();
+        assert_eq!(vec![0, 3, 2, 1], prog.memory);
+        assert_eq!(1, prog.ptr);
+
+        // optimized moves can jump more than a whole tape length at once
+        let mut prog: Program = Program::with_tape_size(optimize::all(Program::compile(">>>>>>>>>>+<<<<<<<<<<<<<-", false)), 4);
+        prog.set_tape_mode(TapeMode::Wrapping);
+        prog.run(&mut empty(), &mut Vec::new()).unwrap();
+        assert_eq!(vec![0, 255, 1, 0], prog.memory);
+        assert_eq!(1, prog.ptr);
+    }
+
+    #[test]
+    fn lenient_moves() {
+        let mut prog: Program = Program::with_tape_size(Program::compile("<+++.>>>>-.", false), 3);
+        match prog.run_to_vec(&[]) {
+            Err(BfError::PointerUnderflow { pc: 0, .. }) => {},
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        let mut prog: Program = Program::with_tape_size(Program::compile("<+++.>>>>-.", false), 3);
+        prog.set_lenient(true);
+        assert_eq!(vec![3, 255], prog.run_to_vec(&[]).unwrap());
+        assert_eq!(vec![3, 0, 255], prog.memory);
+        assert_eq!(2, prog.ptr);
+
+        // the warnings only go somewhere when there is a sink for them
+        prog.reset();
+        let mut warnings = Vec::new();
+        let mut observers = Observers { warnings: Some(&mut warnings), ..Observers::default() };
+        prog.run_with(&mut empty(), &mut Vec::new(), &Limits::default(), &mut observers).unwrap();
+        assert_eq!(String::from_utf8(warnings).unwrap(), "warning: tape underflow at pc 0, keeping the pointer at cell 0\n\
+            warning: tape overflow at pc 7: pointer 3, keeping the pointer at cell 2\n\
+            warning: tape overflow at pc 8: pointer 3, keeping the pointer at cell 2\n");
+
+        // a MulAdd reaching off the end adds to the last cell and the pointer
+        // stays on the loop cell rather than stepping back from the clamped end
+        let mut prog: Program = Program::with_tape_size(optimize::all(Program::compile(">>+++[->+<<+>]", false)), 3);
+        prog.set_lenient(true);
+        let mut warnings = Vec::new();
+        let mut observers = Observers { warnings: Some(&mut warnings), ..Observers::default() };
+        prog.run_with(&mut empty(), &mut Vec::new(), &Limits::default(), &mut observers).unwrap();
+        assert_eq!(vec![0, 3, 0], prog.memory);
+        assert_eq!(2, prog.ptr);
+        assert_eq!(String::from_utf8(warnings).unwrap(), "warning: tape overflow at pc 2: pointer 3, keeping the pointer at cell 2\n");
+    }
+
+    #[test]
+    fn start_ptr() {
+        let mut prog = Program::from_str(",<++>>+++<.");
+        prog.set_start_ptr(10).unwrap();
+        assert_eq!(b"a".to_vec(), prog.run_to_vec(b"a").unwrap());
+        assert_eq!(&[2, 97, 3], &prog.memory[9..12]);
+        assert_eq!(10, prog.ptr);
+        assert_eq!(11, prog.stats().max_ptr);
+
+        prog.reset();
+        assert_eq!(10, prog.ptr);
+        assert_eq!(0, prog.memory[10]);
+
+        // the cells left of the start are there to be used
+        let mut prog = Program::from_str("<<<+");
+        prog.set_start_ptr(3).unwrap();
+        prog.run_to_vec(&[]).unwrap();
+        assert_eq!(1, prog.memory[0]);
+
+        let mut prog: Program = Program::with_tape_size(Vec::new(), 4);
+        match prog.set_start_ptr(4) {
+            Err(BfError::StartOffTape { ptr: 4, tape_size: 4 }) => {},
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(0, prog.ptr);
+    }
+
+    #[test]
+    fn cancel() {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&cancel);
+        let stopper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            flag.store(true, Ordering::Relaxed);
+        });
+
+        let mut prog = Program::from_str("+.[]");
+        let mut output = Vec::new();
+        let start = Instant::now();
+        match prog.run_cancellable(&mut empty(), &mut output, &cancel) {
+            Err(BfError::Cancelled { pc: 2..=3 }) => {},
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(output, vec![1]);
+        stopper.join().unwrap();
+    }
+
+    #[test]
+    fn histogram() {
+        let mut prog: Program = Program::with_tape_size(Program::compile("+>+>>+++>-", false), 8);
+        prog.run(&mut empty(), &mut Vec::new()).unwrap();
+        let counts = prog.histogram();
+        assert_eq!(Some(&4), counts.get(&0));
+        assert_eq!(Some(&2), counts.get(&1));
+        assert_eq!(Some(&1), counts.get(&3));
+        assert_eq!(Some(&1), counts.get(&255));
+        assert_eq!(prog.histogram_summary(), format!("4 zero cells, 4 nonzero cells\n{:>12} {:>12}\n{:>12} {:>12}\n{:>12} {:>12}\n",
+            1, 2, 3, 1, 255, 1));
+    }
+
+    #[test]
+    fn step_limit() {
+        let mut prog = Program::from_str("+[]");
+        let err = prog.run_with_limit(&mut empty(), &mut Vec::new(), 100).unwrap_err();
+        assert_eq!(err.to_string(), "step limit of 100 exceeded at pc 2");
+
+        // a program that finishes within the limit is unaffected
+        let mut prog = Program::from_str("+++");
+        prog.run_with_limit(&mut empty(), &mut Vec::new(), 3).unwrap();
+        assert_eq!(3, prog.memory[0]);
+    }
+
+    #[test]
+    fn output_limit() {
+        let mut prog = Program::from_str("+[.+]");
+        let mut output = Vec::new();
+        let err = prog.run_with_limits(&mut empty(), &mut output, &Limits { max_output: Some(5), ..Limits::default() }).unwrap_err();
+        assert_eq!(err.to_string(), "output limit of 5 exceeded at pc 2");
+        assert_eq!(output, vec![1, 2, 3, 4, 5]);
+
+        let mut prog = Program::from_str("+..");
+        prog.run_with_limits(&mut empty(), &mut Vec::new(), &Limits { max_output: Some(2), ..Limits::default() }).unwrap();
+    }
+
+    #[test]
+    fn timeout() {
+        let mut prog = Program::from_str("+[]");
+        let start = Instant::now();
+        let err = prog.run_with_timeout(&mut empty(), &mut Vec::new(), Duration::from_millis(50)).unwrap_err();
+        let elapsed = start.elapsed();
+
+        assert!(err.to_string().starts_with("timed out after 50ms"), "{}", err);
+        assert!(elapsed >= Duration::from_millis(50));
+        assert!(elapsed < Duration::from_secs(5), "took {:?}", elapsed);
+    }
+
+    #[test]
+    fn run_until_output() {
+        let mut prog = Program::from_str(",.,.,.,.");
+        let mut input: &[u8] = b"ab>c";
+        let mut output = Vec::new();
+        assert_eq!(StepResult::Continue, prog.run_until_output(&mut input, &mut output, b'>').unwrap());
+        assert_eq!(output, b"ab>");
+        assert_eq!(prog.pc(), 6);
+
+        // the end of the program comes before another '>'
+        assert_eq!(StepResult::Halted, prog.run_until_output(&mut input, &mut output, b'>').unwrap());
+        assert_eq!(output, b"ab>c");
+    }
+
+    #[test]
+    fn single_step() {
+        let mut prog = Program::from_str("+>,.");
+        let mut input: &[u8] = b"A";
+        let mut output = Vec::new();
+
+        assert_eq!(StepResult::Continue, prog.step(&mut input, &mut output).unwrap());
+        assert_eq!((1, 0, 1), (prog.pc(), prog.pointer(), prog.memory[0]));
+        assert_eq!(StepResult::Continue, prog.step(&mut input, &mut output).unwrap());
+        assert_eq!((2, 1), (prog.pc(), prog.pointer()));
+        assert_eq!(StepResult::Continue, prog.step(&mut input, &mut output).unwrap());
+        assert_eq!(StepResult::Halted, prog.step(&mut input, &mut output).unwrap());
+        assert_eq!(b"A".to_vec(), output);
+
+        // stepping a halted program does nothing
+        assert_eq!(StepResult::Halted, prog.step(&mut input, &mut output).unwrap());
+        assert_eq!(4, prog.pc());
+
+        let mut empty_prog = Program::from_str("");
+        assert_eq!(StepResult::Halted, empty_prog.step(&mut empty(), &mut output).unwrap());
+        empty_prog.run(&mut empty(), &mut output).unwrap();
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        let mut prog = Program::from_str("++++[>+++++++++++<-]>[.+>,<]");
+        let input = b"abc";
+        let mut reader: &[u8] = input;
+        let mut output = Vec::new();
+        prog.run_with_limit(&mut reader, &mut output, 80).unwrap_err();
+        assert!(!output.is_empty() && !reader.is_empty());
+        let state = prog.snapshot();
+        let read = input.len() - reader.len();
+
+        prog.run(&mut reader, &mut Vec::new()).unwrap();
+        prog.restore(state.clone());
+        assert_eq!(state, prog.snapshot());
+
+        // continuing from the snapshot gives the same output as an uninterrupted run
+        let mut reader = &input[read..];
+        prog.run(&mut reader, &mut output).unwrap();
+        let mut fresh = Program::from_str("++++[>+++++++++++<-]>[.+>,<]");
+        assert_eq!(fresh.run_to_vec(input).unwrap(), output);
+        assert_eq!(fresh.snapshot(), prog.snapshot());
+    }
+
+    #[test]
+    fn step_back() {
+        let commands = optimize::all(Program::compile("+++[>++<-]>,<<+.", false));
+        let mut prog: Program = Program::with_tape_size(commands, 2);
+        prog.set_tape_mode(TapeMode::Bidirectional);
+        assert!(!prog.step_back());
+        prog.enable_history();
+        assert!(!prog.step_back());
+
+        let mut input: &[u8] = b"A";
+        let mut output = Vec::new();
+        prog.step(&mut input, &mut output).unwrap();
+        let snapshot = (prog.pc, prog.ptr, prog.origin, prog.memory.clone(), prog.stats());
+
+        // the rest runs a loop, reads input and grows the tape to the left
+        let mut forward = 0;
+        while prog.step(&mut input, &mut output).unwrap() != StepResult::Halted {
+            forward += 1;
+        }
+        assert_eq!(vec![1], output);
+        assert_eq!(vec![0, 1, 0, 65], prog.memory);
+        for _ in 0..=forward {
+            assert!(prog.step_back());
+        }
+        assert_eq!(snapshot, (prog.pc, prog.ptr, prog.origin, prog.memory.clone(), prog.stats()));
+
+        assert!(prog.step_back());
+        assert_eq!((0, vec![0, 0]), (prog.pc, prog.memory.clone()));
+        assert!(!prog.step_back());
+        // profiling turned on between a step and stepping back
+        let mut prog = Program::from_str("+");
+        prog.enable_history();
+        prog.step(&mut empty(), &mut Vec::new()).unwrap();
+        prog.enable_profiling();
+        assert!(prog.step_back());
+        assert_eq!(Some(&[0][..]), prog.profile());
+
+        // a MulAdd clamped to the end of the tape is undone on the cell it wrote
+        let mut prog: Program = Program::with_tape_size(optimize::all(Program::compile(">+++[-<<+>>]", false)), 3);
+        prog.set_lenient(true);
+        prog.enable_history();
+        let mut steps = 0;
+        while prog.step(&mut empty(), &mut Vec::new()).unwrap() != StepResult::Halted {
+            steps += 1;
+        }
+        assert_eq!(vec![3, 0, 0], prog.memory);
+        for _ in 0..=steps {
+            assert!(prog.step_back());
+        }
+        assert_eq!(vec![0, 0, 0], prog.memory);
+    }
+
+    #[test]
+    fn reset() {
+        let mut prog = Program::from_str(",[.,]+>>");
+        prog.set_eof_mode(EofMode::Zero);
+
+        let mut first = Vec::new();
+        prog.run(&mut &b"abc"[..], &mut first).unwrap();
+        prog.reset();
+        assert_eq!(Program::from_str(",[.,]+>>").memory, prog.memory);
+        assert_eq!((0, 0), (prog.pc(), prog.pointer()));
+
+        let mut second = Vec::new();
+        prog.run(&mut &b"abc"[..], &mut second).unwrap();
+        assert_eq!(b"abc".to_vec(), first);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn optimized_moves() {
+        let mut prog: Program = Program::with_tape_size(vec![Command::Move(3), Command::Add(-2)], 4);
+        prog.run(&mut empty(), &mut Vec::new()).unwrap();
+        assert_eq!(vec![0, 0, 0, 254], prog.memory);
+
+        let mut prog: Program = Program::with_tape_size(vec![Command::Move(4)], 4);
+        let err = prog.run(&mut empty(), &mut Vec::new()).unwrap_err();
+        assert_eq!(err.to_string(), "tape overflow at pc 0: pointer 4");
+
+        let mut prog: Program = Program::with_tape_size(vec![Command::Move(-3), Command::Add(1)], 2);
+        prog.set_tape_mode(TapeMode::Bidirectional);
+        prog.run(&mut empty(), &mut Vec::new()).unwrap();
+        assert_eq!(1, prog.memory[prog.origin - 3]);
+    }
+
+    // a writer that accepts a single byte per call and is interrupted
+    // on every other call, like a congested pipe
+    struct ChunkedWriter {
+        written: Vec<u8>,
+        interrupt: bool,
+    }
 
-impl Program {
-    // compile will compile the given string as a brainfuck program
-    // if debug_pound is true then '#' will be treated as a debug command
-    pub fn compile(input: &str, debug_pound: bool) -> Vec<Command> {
-        let mut coms = Vec::new();
-        for c in input.chars() {
-            let command = match c {
-                '>' => Command::Right,
-                '<' => Command::Left,
-                '+' => Command::Inc,
-                '-' => Command::Dec,
-                '.' => Command::Out,
-                ',' => Command::In,
-                '[' => Command::JmpFwd,
-                ']' => Command::JmpBack,
-                '#' => if debug_pound { Command::Debug } else { continue },
-                _ => continue,
-            };
-            coms.push(command);
+    impl Write for ChunkedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted"));
+            }
+            match buf.first() {
+                Some(&b) => {
+                    self.written.push(b);
+                    Ok(1)
+                },
+                None => Ok(0),
+            }
         }
 
-        coms
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
     }
 
-    pub fn new(commands: Vec<Command>) -> Program {
-        // build jump table
-        let mut jmps = Vec::new();
+    #[test]
+    fn chunked_output() {
+        let raw = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+        let mut prog = Program::from_str(raw);
+        let mut output = ChunkedWriter { written: Vec::new(), interrupt: false };
+        prog.run(&mut empty(), &mut output).unwrap();
+        assert_eq!(output.written, b"Hello World!\n");
+    }
+
+    #[test]
+    fn buffered_output() {
+        // everything is written out by the time run returns
+        let mut prog = Program::from_str("++++++++[>++++++++<-]>+>++++++++++[>++++++++++++++++++++<-]>[<<.>>-]");
+        let output = prog.run_to_vec(&[]).unwrap();
+        assert_eq!(output, vec![b'A'; 200]);
+
+        // output is flushed before reading so prompts show up first
+        struct Prompted<'a>(&'a RefCell<Vec<u8>>);
+        impl<'a> Read for Prompted<'a> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                assert_eq!(*self.0.borrow(), b"?");
+                buf[0] = b'x';
+                Ok(1)
+            }
+        }
+        struct Shared<'a>(&'a RefCell<Vec<u8>>);
+        impl<'a> Write for Shared<'a> {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.borrow_mut().write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let written = RefCell::new(Vec::new());
+        let mut prog = Program::from_str("+++++++++++++[>+++++<-]>-- .,.");
+        prog.run(&mut Prompted(&written), &mut Shared(&written)).unwrap();
+        assert_eq!(*written.borrow(), b"?x");
+    }
+
+    #[test]
+    fn trace() {
+        let mut prog = Program::from_str("++[-]>");
+        let mut trace = Vec::new();
+        prog.run_traced(&mut empty(), &mut Vec::new(), &mut trace).unwrap();
+
+        let trace = String::from_utf8(trace).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(lines, vec![
+            "pc=0 ptr=0 cell=0 Inc",
+            "pc=1 ptr=0 cell=1 Inc",
+            "pc=2 ptr=0 cell=2 JmpFwd(4)",
+            "pc=3 ptr=0 cell=2 Dec",
+            "pc=4 ptr=0 cell=1 JmpBack(2)",
+            "pc=3 ptr=0 cell=1 Dec",
+            "pc=4 ptr=0 cell=0 JmpBack(2)",
+            "pc=5 ptr=0 cell=0 Right",
+        ]);
+    }
+
+    #[test]
+    fn trace_watched_cells() {
+        let mut prog: Program = Program::with_tape_size(Program::compile("+>>++<", false), 3);
+        prog.watch(&[0, 2, 3]);
+        let mut trace = Vec::new();
+        prog.run_traced(&mut empty(), &mut Vec::new(), &mut trace).unwrap();
+
+        let trace = String::from_utf8(trace).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(lines, vec![
+            "pc=0 ptr=0 cell=0 Inc [0]=0 [2]=0",
+            "pc=1 ptr=0 cell=1 Right [0]=1 [2]=0",
+            "pc=2 ptr=1 cell=0 Right [0]=1 [2]=0",
+            "pc=3 ptr=2 cell=0 Inc [0]=1 [2]=0",
+            "pc=4 ptr=2 cell=1 Inc [0]=1 [2]=1",
+            "pc=5 ptr=2 cell=2 Left [0]=1 [2]=2",
+        ]);
+    }
+
+    #[test]
+    fn annotate_outputs() {
+        let tokens = Program::tokenize("++.\n+.[-]\n++++++++++.", false);
+        let mut prog = Program::new(tokens.iter().map(|t| t.0.clone()).collect());
+        let mut output = Vec::new();
+        let mut outputs = Vec::new();
+        {
+            let mut observers = Observers { outputs: Some(&mut outputs), ..Observers::default() };
+            prog.run_with(&mut empty(), &mut output, &Limits::default(), &mut observers).unwrap();
+        }
+        assert_eq!(output, [2, 3, 10]);
+        assert_eq!(String::from_utf8(outputs).unwrap(), "pc=2 byte=2\npc=4 byte=3\npc=18 byte=10\n");
+
+        prog.reset();
+        prog.set_positions(tokens.into_iter().map(|t| t.1).collect());
+        let mut outputs = Vec::new();
+        {
+            let mut observers = Observers { outputs: Some(&mut outputs), ..Observers::default() };
+            prog.run_with(&mut empty(), &mut Vec::new(), &Limits::default(), &mut observers).unwrap();
+        }
+        assert_eq!(String::from_utf8(outputs).unwrap(),
+            "pc=2 line=1 column=3 byte=2\npc=4 line=2 column=2 byte=3\npc=18 line=3 column=11 byte=10\n");
+
+        let mut prog = Program::from_str("++++++++[>++++++++<-]>+.");
+        let mut outputs = Vec::new();
+        {
+            let mut observers = Observers { outputs: Some(&mut outputs), ..Observers::default() };
+            prog.run_with(&mut empty(), &mut Vec::new(), &Limits::default(), &mut observers).unwrap();
+        }
+        assert_eq!(String::from_utf8(outputs).unwrap(), "pc=23 byte=65 'A'\n");
+    }
+
+    #[test]
+    fn echo() {
+        let mut prog = Program::from_str(",[.,]");
+        prog.set_eof_mode(EofMode::Zero);
+        let mut output = Vec::new();
+        let mut echo = Vec::new();
+        {
+            let mut observers = Observers { echo: Some(&mut echo), ..Observers::default() };
+            prog.run_with(&mut &b"hi"[..], &mut output, &Limits::default(), &mut observers).unwrap();
+        }
+        assert_eq!(output, b"hi");
+        assert_eq!(echo, b"hi");
+    }
+
+    #[test]
+    fn tape_view() {
+        let mut prog = Program::from_str("+++.>++.>>>>>>+.");
+        let mut tape = Vec::new();
+        {
+            let mut observers = Observers { tape: Some(&mut tape), ..Observers::default() };
+            prog.run_with(&mut empty(), &mut Vec::new(), &Limits::default(), &mut observers).unwrap();
+        }
+        assert_eq!(String::from_utf8(tape).unwrap(), "0: [3] 0 0 0 0\n\
+            0: 3 [2] 0 0 0 0\n\
+            3: 0 0 0 0 [1] 0 0 0 0\n");
+
+        // the view stops at the end of the tape
+        let mut small: Program = Program::with_tape_size(Program::compile(">>", false), 3);
+        small.run(&mut empty(), &mut Vec::new()).unwrap();
+        assert_eq!(small.tape_view(), "0: 0 0 [0]\n");
+    }
+
+    #[test]
+    fn breakpoints() {
+        let raw = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+        let mut prog = Program::from_str(raw);
+        // the first '.' of the second word
+        let pc = raw.char_indices().filter(|&(_, c)| c == '.').nth(6).unwrap().0;
+        prog.set_breakpoint(pc);
+
+        let mut output = Vec::new();
+        assert_eq!(prog.run(&mut empty(), &mut output).unwrap(), StepResult::Breakpoint(pc));
+        assert_eq!(prog.pc(), pc);
+        assert_eq!(output, b"Hello ");
+
+        // resuming runs past the breakpoint instead of stopping on it again
+        assert_eq!(prog.run(&mut empty(), &mut output).unwrap(), StepResult::Halted);
+        assert_eq!(output, b"Hello World!\n");
+    }
+
+    #[test]
+    fn breakpoint_step() {
+        let mut prog = Program::from_str("+++");
+        prog.set_breakpoint(1);
+        assert_eq!(prog.step(&mut empty(), &mut Vec::new()).unwrap(), StepResult::Continue);
+        assert_eq!(prog.step(&mut empty(), &mut Vec::new()).unwrap(), StepResult::Breakpoint(1));
+        assert_eq!(prog.step(&mut empty(), &mut Vec::new()).unwrap(), StepResult::Continue);
+        assert_eq!(prog.pc(), 2);
+
+        // breakpoints fire again on every pass through a loop
+        let mut prog = Program::from_str("++[-]");
+        prog.set_breakpoint(3);
+        assert_eq!(prog.run(&mut empty(), &mut Vec::new()).unwrap(), StepResult::Breakpoint(3));
+        assert_eq!(prog.run(&mut empty(), &mut Vec::new()).unwrap(), StepResult::Breakpoint(3));
+        assert_eq!(prog.run(&mut empty(), &mut Vec::new()).unwrap(), StepResult::Halted);
+        prog.clear_breakpoint(3);
+        prog.reset();
+        assert_eq!(prog.run(&mut empty(), &mut Vec::new()).unwrap(), StepResult::Halted);
+    }
+
+    #[test]
+    fn watchpoints() {
+        let raw = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+        let mut prog = Program::from_str(raw);
+        prog.break_when_cell(2, 72);
+
+        let mut output = Vec::new();
+        let result = prog.run(&mut empty(), &mut output).unwrap();
+        assert_eq!(result, StepResult::Watchpoint { cell: 2, value: 72 });
+        assert_eq!(prog.memory()[2], 72);
+        assert!(output.is_empty());
+
+        assert_eq!(prog.run(&mut empty(), &mut output).unwrap(), StepResult::Halted);
+        assert_eq!(output, b"Hello World!\n");
+
+        // cells written by a multiply loop are watched too
+        let mut prog = Program::new(optimize::all(Program::compile("+++[->++<]", false)));
+        prog.break_when_cell(1, 6);
+        assert_eq!(prog.step(&mut empty(), &mut Vec::new()).unwrap(), StepResult::Continue);
+        assert_eq!(prog.step(&mut empty(), &mut Vec::new()).unwrap(), StepResult::Watchpoint { cell: 1, value: 6 });
+
+        // wider cells can be watched for values a byte can't hold
+        let mut prog: Program<u16> = Program::with_cells(Program::compile("++++++++++++++++[>++++++++++++++++<-]>+", false));
+        prog.break_when_cell(1, 256);
+        assert_eq!(prog.run(&mut empty(), &mut Vec::new()).unwrap(), StepResult::Watchpoint { cell: 1, value: 256 });
+        assert_eq!(prog.run(&mut empty(), &mut Vec::new()).unwrap(), StepResult::Halted);
+        assert_eq!(prog.memory()[1], 257);
+        // the cell a MulAdd writes is found the way it is when running
+        let commands = optimize::all(Program::compile(">>+++[->+<]", false));
+        let mut prog: Program = Program::with_tape_size(commands.clone(), 3);
+        prog.set_tape_mode(TapeMode::Wrapping);
+        prog.break_when_cell(0, 3);
+        assert_eq!(prog.run(&mut empty(), &mut Vec::new()).unwrap(), StepResult::Watchpoint { cell: 0, value: 3 });
+        let mut prog: Program = Program::with_tape_size(optimize::all(Program::compile(">+++[-<<+>>]", false)), 3);
+        prog.set_lenient(true);
+        prog.break_when_cell(0, 3);
+        assert_eq!(prog.run(&mut empty(), &mut Vec::new()).unwrap(), StepResult::Watchpoint { cell: 0, value: 3 });
+    }
+
+    #[test]
+    fn memory() {
+        let mut prog = Program::from_str("+++>++>+<");
+        prog.run(&mut empty(), &mut Vec::new()).unwrap();
+        assert_eq!(&prog.memory()[..4], &[3, 2, 1, 0]);
+        assert_eq!(prog.memory().len(), DEFAULT_TAPE_SIZE);
+        assert_eq!(prog.pointer(), 1);
+        assert_eq!(prog.memory()[prog.pointer()], 2);
+    }
+
+    #[test]
+    fn dump_memory() {
+        let mut prog = Program::from_str("++++++++[>++++++++<-]>+>++++++++++");
+        prog.run(&mut empty(), &mut Vec::new()).unwrap();
+        assert_eq!(prog.dump_memory(0, 4), format!("00000000  {:<47}  |.A..|\n", "00 41 0a 00"));
+        assert_eq!(prog.dump_memory(1, 17), format!("00000001  41 0a{}  |A{}|\n00000011  00{:45}  |.|\n",
+            " 00".repeat(14), ".".repeat(15), ""));
+
+        // ranges are clamped to the tape
+        assert_eq!(prog.dump_memory(29999, 10), format!("0000752f  {:<47}  |.|\n", "00"));
+        assert_eq!(prog.dump_memory(40000, 10), "");
+        assert_eq!(prog.dump_memory(0, 0), "");
+
+        let mut wide: Program<u16> = Program::with_cells(Program::compile("+>-", false));
+        wide.run(&mut empty(), &mut Vec::new()).unwrap();
+        assert_eq!(wide.dump_memory(0, 2), format!("00000000  {:<79}  |..|\n", "0001 ffff"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let raw = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+        let mut prog = Program::new(optimize::all(Program::compile(raw, false)));
+        prog.set_eof_mode(EofMode::Zero);
+
+        let mut decimal = Program::from_str("+++.");
+        decimal.set_output_mode(OutputMode::Decimal);
+        let mut loaded: Program = serde_json::from_str(&serde_json::to_string(&decimal).unwrap()).unwrap();
+        assert_eq!(loaded.run_to_vec(&[]).unwrap(), b"3 ");
+
+        let json = serde_json::to_string(&prog).unwrap();
+        let mut loaded: Program = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, prog);
+        assert_eq!(loaded.run_to_vec(&[]).unwrap(), b"Hello World!\n");
+
+        let path = std::env::temp_dir().join(format!("brainfuck-serde-{}.json", std::process::id()));
+        prog.save(&path).unwrap();
+        let mut loaded: Program = Program::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.run_to_vec(&[]).unwrap(), b"Hello World!\n");
+
+        // jump targets are rebuilt so unbalanced programs are still rejected
+        assert!(serde_json::from_str::<Program>(r#"{"commands":[{"JmpFwd":0}],"tape_size":1,"eof_mode":"Zero","tape_mode":"Fixed"}"#).is_err());
+    }
+
+    #[test]
+    fn profile() {
+        let mut prog = Program::from_str("+++[>++<-]");
+        assert_eq!(prog.profile(), None);
+        prog.enable_profiling();
+        prog.run(&mut empty(), &mut Vec::new()).unwrap();
+
+        // the loop body and closing bracket run once per iteration
+        assert_eq!(prog.profile().unwrap(), &[1, 1, 1, 1, 3, 3, 3, 3, 3, 3]);
+        let summary = prog.profile_summary();
+        let lines: Vec<&str> = summary.lines().collect();
+        assert_eq!(lines[0], "hottest commands:");
+        assert_eq!(lines[1], "       4            3  Right");
+        assert_eq!(lines[11], "by command:");
+        assert_eq!(lines[12], "     Inc            9");
+        assert_eq!(lines.len(), 18);
+
+        prog.reset();
+        assert_eq!(prog.profile().unwrap(), &[0; 10]);
+    }
+
+    #[test]
+    fn tokenize() {
+        let raw = "set ++\nloop [-] é>\n# done";
+        let pos = |offset, line, column| Position { offset, line, column };
+        assert_eq!(Program::tokenize(raw, true), vec![
+            (Command::Inc, pos(4, 1, 5)),
+            (Command::Inc, pos(5, 1, 6)),
+            (Command::JmpFwd(0), pos(12, 2, 6)),
+            (Command::Dec, pos(13, 2, 7)),
+            (Command::JmpBack(0), pos(14, 2, 8)),
+            (Command::Right, pos(18, 2, 11)),
+            (Command::Debug, pos(20, 3, 1)),
+        ]);
+        assert_eq!(Program::tokenize(raw, false).len(), 6);
+
+        // bracket errors can be traced back to the source
+        let tokens = Program::tokenize("+\n+[\n", false);
+        let commands = tokens.iter().map(|t| t.0.clone()).collect();
+        match Program::try_new(commands) {
+            Err(BracketError::UnmatchedOpen(i)) => assert_eq!(tokens[i].1, pos(3, 2, 2)),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn tokenize_strict() {
+        let map = CommandMap::standard(false);
+        let tokens = Program::tokenize_strict("+ [\t-\n]", &map).unwrap();
+        assert_eq!(tokens, Program::tokenize("+ [\t-\n]", false));
+
+        let pos = Position { offset: 7, line: 2, column: 2 };
+        assert_eq!(Program::tokenize_strict("++[-]\n>x<", &map), Err(ParseError { found: 'x', position: pos }));
+        assert_eq!(Program::tokenize("++[-]\n>x<", false).len(), 7);
+
+        // comment regions are still allowed
+        let map = map.with_comment_region('{', '}');
+        assert!(Program::tokenize_strict("+{ a comment }-", &map).is_ok());
+    }
+
+    #[test]
+    fn jump_targets() {
+        let raw = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+        let prog = Program::from_str(raw);
+
+        // match the brackets in the source the way the old jump table did
         let mut table = HashMap::new();
-        for (i, c) in commands.iter().enumerate() {
+        let mut open = Vec::new();
+        for (i, c) in raw.chars().enumerate() {
             match c {
-                Command::JmpFwd => jmps.push(i),
-                Command::JmpBack => {
-                    let start = jmps.pop().unwrap();
+                '[' => open.push(i),
+                ']' => {
+                    let start = open.pop().unwrap();
                     table.insert(start, i);
                     table.insert(i, start);
                 },
@@ -63,201 +2729,212 @@ impl Program {
             }
         }
 
-        Program {
-            commands: commands,
-            memory: vec![0; 30000],
-            jmptable: table,
+        let mut jumps = 0;
+        for (pc, command) in prog.commands.iter().enumerate() {
+            match *command {
+                Command::JmpFwd(target) | Command::JmpBack(target) => {
+                    assert_eq!(table[&pc], target);
+                    jumps += 1;
+                },
+                _ => {},
+            }
         }
+        assert_eq!(jumps, table.len());
+
+        // stale targets from an earlier pass are recomputed
+        let prog = Program::new(vec![Command::JmpFwd(7), Command::Dec, Command::JmpBack(7)]);
+        assert_eq!(prog.commands, vec![Command::JmpFwd(2), Command::Dec, Command::JmpBack(0)]);
     }
 
-    pub fn from_str(input: &str) -> Program {
-        Self::new(Self::compile(input, false))
+    #[test]
+    fn listing() {
+        let prog = Program::from_str("+[->+<]");
+        assert_eq!(prog.listing(), "0 Inc\n1 JmpFwd 6\n2 Dec\n3 Right\n4 Inc\n5 Left\n6 JmpBack 1\n");
+
+        let prog = Program::new(optimize::all(Program::compile("++>>[->+++<]", false)));
+        assert_eq!(prog.listing(), "0 Add 2\n1 Move 2\n2 MulAdd 1:3\n");
     }
 
-    pub fn run<R: Read, W: Write>(&mut self, input: &mut R, output: &mut W) -> io::Result<()> {
-        let mut input = input.bytes();
+    #[test]
+    fn canonical_source() {
+        let raw = "+[->+<]#,.";
+        assert_eq!(Program::new(Program::compile(raw, true)).to_string(), raw);
 
-        let mut ptr = 0;
-        let mut pc = 0;
-        loop {
-            match self.commands[pc] {
-                Command::Right => ptr += 1,
-                Command::Left => ptr -= 1,
-                Command::Inc => self.memory[ptr] += 1,
-                Command::Dec => {
-                    self.memory[ptr] -= 1;
-                },
-                Command::Out => {
-                    output.write(&[self.memory[ptr]])?;
-                },
-                Command::In => match input.next() {
-                    Some(res) => {
-                        self.memory[ptr] = res?;
-                    },
-                    None => {}, // EOF, do nothing for now
-                },
-                Command::JmpFwd => {
-                    if self.memory[ptr] == 0 {
-                        pc = self.jmptable[&pc];
-                    }
-                },
-                Command::JmpBack => {
-                    if self.memory[ptr] != 0 {
-                        pc = self.jmptable[&pc];
-                    }
-                },
-                Command::Debug => self.debug(ptr, pc),
-            }
-            pc += 1;
-            
-            if pc >= self.commands.len() {
-                break;
+        // optimized commands are spelled out in full
+        let raw = "+++>>--[-]<<[->>+++<<]<[->>-<<]>>[->+>--<<]";
+        let prog = Program::new(optimize::all(Program::compile(raw, false)));
+        assert!(prog.commands().any(|c| c.kind() == "MulAdd"));
+        assert_eq!(prog.to_string(), "+++>>--[-]<<[->>+++<<]<[->>-<<]>>[->+>--<<]");
+    }
+
+    #[test]
+    fn commands() {
+        let raw = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+        let prog = Program::from_str(raw);
+        let incs = prog.commands().filter(|&c| *c == Command::Inc).count();
+        assert_eq!(incs, raw.matches('+').count());
+
+        let mut jumps = 0;
+        for command in &prog {
+            if let Command::JmpFwd(_) | Command::JmpBack(_) = *command {
+                jumps += 1;
             }
         }
+        assert_eq!(jumps, 6);
+        assert_eq!(prog.commands().len(), raw.len());
+    }
 
-        Ok(())
+    #[test]
+    fn run_to_vec() {
+        let raw = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+        let output = Program::from_str(raw).run_to_vec(&[]).unwrap();
+        assert_eq!(b"Hello World!\n".to_vec(), output);
+
+        let mut echo = Program::from_str(",[.,]");
+        echo.set_eof_mode(EofMode::Zero);
+        assert_eq!(b"echo".to_vec(), echo.run_to_vec(b"echo").unwrap());
     }
 
-    // print debug information
-    fn debug(&self, ptr: usize, pc: usize) {
-        let com_len = self.commands.len();
-        let mem_len = self.memory.len();
-        let pre_com = if pc > 3 {
-            &self.commands[pc-3..pc]
-        } else {
-            &self.commands[0..pc]
-        };
-        let post_com = &self.commands[usize::min(pc+1, com_len)..usize::min(pc+3, com_len)];
-        let pre_mem = if ptr > 3 {
-            &self.memory[ptr-3..ptr]
-        } else {
-            &self.memory[0..ptr]
-        };
-        let post_mem = &self.memory[usize::min(ptr+1, mem_len)..usize::min(ptr+3, mem_len)];
+    #[test]
+    fn input_bytes() {
+        let mut output = Vec::new();
+        let mut echo = Program::from_str(",.,.,.");
+        echo.run_with_input_bytes(b"ab", &mut output).unwrap();
+        assert_eq!(output, b"abb");
 
-        println!("--------------------------");
-        println!("PC: {} | PTR: {}", pc, ptr);
-        println!("COMS: {:?} -> {:?} <- {:?}", pre_com, self.commands[pc], post_com);
-        println!("MEM: {:?} -> {:?} <- {:?}", pre_mem, self.memory[ptr], post_mem);
-        println!("--------------------------");
+        output.clear();
+        echo.reset();
+        echo.set_eof_mode(EofMode::Zero);
+        echo.run_with_input_bytes(b"ab", &mut output).unwrap();
+        assert_eq!(output, b"ab\0");
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use std::io::empty;
+    #[test]
+    fn compile_bytes() {
+        let raw = b"+\xff+\xc3[\xe2\x82-]\x80>#";
+        let expected = vec![Command::Inc, Command::Inc, Command::JmpFwd(0), Command::Dec, Command::JmpBack(0), Command::Right];
+        assert_eq!(Program::compile_bytes(raw, false), expected);
+        assert_eq!(Program::compile_bytes(raw, true).last(), Some(&Command::Debug));
+
+        // the same as going through a command map
+        let raw = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++. # done";
+        for &debug in [false, true].iter() {
+            let expected = Program::compile_with(raw, &CommandMap::standard(debug));
+            assert_eq!(Program::compile_bytes(raw.as_bytes(), debug), expected);
+            assert_eq!(Program::compile(raw, debug), expected);
+        }
+    }
 
     #[test]
-    fn hello_world() {
-        let raw = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+    fn compile_file() {
+        let path = std::env::temp_dir().join(format!("brainfuck-compile-file-{}.b", std::process::id()));
+        std::fs::write(&path, b"+++\xff[>++<-]>.").unwrap();
+        let mut prog = Program::compile_file(&path, false).unwrap();
+        assert_eq!(prog.run_to_vec(&[]).unwrap(), vec![6]);
 
-        let mut output = Vec::new();
-        let mut compiled = Program::from_str(raw);
-        compiled.run(&mut empty(), &mut output).unwrap();
-        let out: String = output.iter().map(|&b| char::from(b)).collect();
-        assert_eq!(out, "Hello World!\n");
+        std::fs::write(&path, "+[\n").unwrap();
+        let res = Program::compile_file(&path, false);
+        std::fs::remove_file(&path).unwrap();
+        match res {
+            Err(BfError::UnbalancedBrackets(BracketError::UnmatchedOpen(1))) => {},
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        match Program::compile_file(&path, false) {
+            Err(BfError::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => {},
+            res => panic!("unexpected result {:?}", res),
+        }
     }
 
     #[test]
-    fn hello_world_comments() {
-        let raw = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
-        let commented = r" 1 +++++ +++               Set Cell #0 to 8
- 2 [
- 3     >++++               Add 4 to Cell #1; this will always set Cell #1 to 4
- 4     [                   as the cell will be cleared by the loop
- 5         >++             Add 4*2 to Cell #2
- 6         >+++            Add 4*3 to Cell #3
- 7         >+++            Add 4*3 to Cell #4
- 8         >+              Add 4 to Cell #5
- 9         <<<<-           Decrement the loop counter in Cell #1
-10     ]                   Loop till Cell #1 is zero
-11     >+                  Add 1 to Cell #2
-12     >+                  Add 1 to Cell #3
-13     >-                  Subtract 1 from Cell #4
-14     >>+                 Add 1 to Cell #6
-15     [<]                 Move back to the first zero cell you find; this will
-16                         be Cell #1 which was cleared by the previous loop
-17     <-                  Decrement the loop Counter in Cell #0
-18 ]                       Loop till Cell #0 is zero
-19 
-20 The result of this is:
-21 Cell No :   0   1   2   3   4   5   6
-22 Contents:   0   0  72 104  88  32   8
-23 Pointer :   ^
-24 
-25 >>.                     Cell #2 has value 72 which is 'H'
-26 >---.                   Subtract 3 from Cell #3 to get 101 which is 'e'
-27 +++++ ++..+++.          Likewise for 'llo' from Cell #3
-28 >>.                     Cell #5 is 32 for the space
-29 <-.                     Subtract 1 from Cell #4 for 87 to give a 'W'
-30 <.                      Cell #3 was set to 'o' from the end of 'Hello'
-31 +++.----- -.----- ---.  Cell #3 for 'rl' and 'd'
-32 >>+.                    Add 1 to Cell #5 gives us an exclamation point
-33 >++.                    And finally a newline from Cell #6";
+    fn compile_reader() {
+        let source = "++[>+<-] a comment # with >. in it\n,[.,]";
+        // a tiny buffer makes the reader refill part way through the source
+        let reader = BufReader::with_capacity(4, Cursor::new(source.as_bytes()));
+        assert_eq!(Program::compile(source, true), Program::compile_reader(reader, true).unwrap());
+        assert_eq!(Program::compile(source, false), Program::compile_reader(Cursor::new(source), false).unwrap());
 
-        let mut raw_compiled = Program::from_str(raw);
-        let mut commented_compiled = Program::from_str(commented);
-        assert_eq!(raw_compiled, commented_compiled);
+        // positions line up with tokenize, past characters of more than one byte too
+        let source = "+ é\n€[-]>.";
+        let reader = BufReader::with_capacity(3, Cursor::new(source.as_bytes()));
+        assert_eq!(Program::tokenize(source, false), Program::tokenize_reader(reader, false).unwrap());
+    }
 
-        let mut raw_output = Vec::new();
-        let mut commented_output = Vec::new();
+    #[test]
+    fn matching_bracket() {
+        let prog = Program::from_str("+[>[-]<[>+[<]]]-");
+        assert_eq!(Some(14), prog.matching_bracket(1));
+        assert_eq!(Some(1), prog.matching_bracket(14));
+        assert_eq!(Some(5), prog.matching_bracket(3));
+        assert_eq!(Some(13), prog.matching_bracket(7));
+        assert_eq!(Some(10), prog.matching_bracket(12));
+        assert_eq!(None, prog.matching_bracket(0));
+        assert_eq!(None, prog.matching_bracket(4));
+        assert_eq!(None, prog.matching_bracket(16));
+    }
 
-        raw_compiled.run(&mut empty(), &mut raw_output).unwrap();
-        commented_compiled.run(&mut empty(), &mut commented_output).unwrap();
+    #[test]
+    fn reads_input() {
+        let echo = Program::from_str(",[.,]");
+        assert!(echo.reads_input());
+        assert_eq!(2, echo.input_command_count());
 
-        let raw_out: String = raw_output.iter().map(|&b| char::from(b)).collect();
-        let commented_out: String = commented_output.iter().map(|&b| char::from(b)).collect();
-        assert_eq!(raw_out, commented_out);
-        assert_eq!(raw_compiled, commented_compiled);
+        let hello = Program::from_str("++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.");
+        assert!(!hello.reads_input());
+        assert_eq!(0, hello.input_command_count());
     }
 
     #[test]
-    fn array_size_test() {
-        // taken from http://www.hevanet.com/cristofd/brainfuck/tests.b
-        // "Goes to cell 30000 and reports from there with a '#'"
-        let raw = "++++[>++++++<-]>[>+++++>+++++++<<-]>>++++<[[>[[>>+<<-]<]>>>-]>-[>+>+<<-]>]
-+++++[>+++++++<<++>-]>.<<.";
-        let mut prog = Program::from_str(raw);
+    fn estimate_cost() {
+        let prog: Program = Program::try_new(Program::compile("++[>+++[>+<-]<-].#", true)).unwrap();
+        assert_eq!(17, prog.estimate_cost(&CostModel::default()));
 
-        let mut output = Vec::new();
-        prog.run(&mut empty(), &mut output).unwrap();
+        // a loop's brackets cost the same as the commands around it
+        let weights = CostModel { moves: 2, io: 10, debug: 5, loop_multiplier: 10, ..CostModel::default() };
+        assert_eq!((2 + 2) + 10 * (2 + 3 + 1 + 2 + 1 + 1) + 100 * (2 + 1 + 2 + 1) + (10 + 5), prog.estimate_cost(&weights));
 
-        assert_eq!('#' as u8, output[0]);
+        let optimized: Program = Program::try_new(optimize::all(Program::compile("++[->+++<]>.", false))).unwrap();
+        assert_eq!(4, optimized.estimate_cost(&CostModel::default()));
     }
 
     #[test]
-    fn obscure_tests() {
-        // taken from http://www.hevanet.com/cristofd/brainfuck/tests.b
-        // "Tests for several obscure problems. Should output an H."
-        let raw = r#"[]++++++++++[>>+>+>++++++[<<+<+++>>>-]<<<<-]
-"A*$";?@![#>>+<<]>[>>]<<<<[>++<[-]]>.>."#;
-        let mut prog = Program::from_str(raw);
+    fn parse() {
+        let mut prog: Program = "+++[>++<-]>.".parse().unwrap();
+        assert_eq!(vec![6], prog.run_to_vec(&[]).unwrap());
 
-        let mut output = Vec::new();
-        prog.run(&mut empty(), &mut output).unwrap();
+        let wide = "+[-]".parse::<Program<u16>>();
+        assert!(wide.is_ok());
+
+        let pos = |offset, line, column| Position { offset, line, column };
+        let unmatched = |error, position| Err(UnmatchedBracket { error, position });
+        assert_eq!(unmatched(BracketError::UnmatchedOpen(1), pos(1, 1, 2)), "+[[]".parse::<Program>());
+        assert_eq!(unmatched(BracketError::UnmatchedClose(0), pos(0, 1, 1)), "]".parse::<Program>());
 
-        assert_eq!('H' as u8, output[0]);
+        // the error gives where the bracket is in the source, not which command it is
+        let err = "hello [+".parse::<Program>().unwrap_err();
+        assert_eq!(err, UnmatchedBracket { error: BracketError::UnmatchedOpen(0), position: pos(6, 1, 7) });
+        assert_eq!(err.to_string(), "unmatched '[' at byte 6 (line 1, column 7)");
+        let err = "a loop [-]\nthen ] stray".parse::<Program>().unwrap_err();
+        assert_eq!(err.position, pos(16, 2, 6));
     }
 
     #[test]
-    fn debug() {
-        // test that programs are compiled correctly concerning the debug flag
-        let raw_simple = "#+.";
-        let comp_simple_debug = Program::compile(raw_simple, true);
-        let comp_simple_no_debug = Program::compile(raw_simple, false);
-        let expected_simple_debug = vec![Command::Debug, Command::Inc, Command::Out];
-        let expected_simple_no_debug = vec![Command::Inc, Command::Out];
-        
-        assert_eq!(expected_simple_debug, comp_simple_debug);
-        assert_eq!(expected_simple_no_debug, comp_simple_no_debug);
+    fn error_kinds() {
+        match Program::from_str("<").run(&mut empty(), &mut Vec::new()) {
+            Err(BfError::PointerUnderflow { pc: 0, .. }) => {},
+            res => panic!("unexpected result {:?}", res),
+        }
+        match Program::from_str("+[>+]").run(&mut empty(), &mut Vec::new()) {
+            Err(BfError::PointerOverflow { pc: 2, ptr: 30000, .. }) => {},
+            res => panic!("unexpected result {:?}", res),
+        }
+        match Program::from_str("+[]").run_with_limit(&mut empty(), &mut Vec::new(), 10) {
+            Err(BfError::StepLimitExceeded { pc: 2, steps: 10 }) => {},
+            res => panic!("unexpected result {:?}", res),
+        }
 
-        // test that debug printing handles edge of array cases without crashing
-        let raw = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.#";
-        let mut prog = Program::new(Program::compile(raw, true));
-        prog.debug(0, 0);
-        prog.debug(30000-1, 0);
-        
-        prog.run(&mut empty(), &mut Vec::new()).unwrap();
+        let err = BfError::from(BracketError::UnmatchedOpen(3));
+        assert_eq!("unmatched '[' at command 3", err.to_string());
+        assert!(err.source().is_some());
     }
 }
@@ -0,0 +1,161 @@
+// The jit backend turns each command into a closure that does its work and
+// returns the pc of the next command, so running the program is a loop of
+// calls rather than a match on every command.
+
+use std::io::{BufWriter, Read, Write};
+
+use cell::Cell;
+use command::Command;
+use error::BfError;
+use machine;
+use program::{Program, Running, StepResult};
+
+// Op executes a single command and gives the pc to continue from
+type Op<C> = Box<dyn Fn(&mut Program<C>, &mut dyn Read, &mut dyn Write) -> Result<usize, BfError>>;
+
+// op pins down the signature of a closure so it can be boxed as an Op
+fn op<C, F>(f: F) -> Op<C>
+    where F: Fn(&mut Program<C>, &mut dyn Read, &mut dyn Write) -> Result<usize, BfError> + 'static {
+    Box::new(f)
+}
+
+// compile picks the ops up front, so checked decides whether arithmetic is
+// checked for the whole run
+fn compile<C: Cell + 'static>(commands: &[Command], checked: bool) -> Vec<Op<C>> {
+    commands.iter().enumerate().map(|(pc, command)| {
+        let next = pc + 1;
+        match *command {
+            Command::Right => op::<C, _>(move |p, _, _| p.move_by(1, None).map(|_| next)),
+            Command::Left => op::<C, _>(move |p, _, _| p.move_by(-1, None).map(|_| next)),
+            Command::Move(delta) => op::<C, _>(move |p, _, _| p.move_by(delta, None).map(|_| next)),
+            Command::Inc | Command::Dec | Command::Add(_) if checked => {
+                let delta = match *command {
+                    Command::Inc => 1,
+                    Command::Dec => -1,
+                    Command::Add(delta) => delta,
+                    _ => unreachable!(),
+                };
+                op::<C, _>(move |p, _, _| p.add_checked(delta).map(|_| next))
+            },
+            Command::Inc => op::<C, _>(move |p, _, _| {
+                p.memory[p.ptr] = p.memory[p.ptr].wrapping_inc();
+                Ok(next)
+            }),
+            Command::Dec => op::<C, _>(move |p, _, _| {
+                p.memory[p.ptr] = p.memory[p.ptr].wrapping_dec();
+                Ok(next)
+            }),
+            Command::Add(delta) => op::<C, _>(move |p, _, _| {
+                p.memory[p.ptr] = p.memory[p.ptr].add_delta(delta);
+                Ok(next)
+            }),
+            Command::Clear => op::<C, _>(move |p, _, _| {
+                p.memory[p.ptr] = C::default();
+                Ok(next)
+            }),
+            Command::MulAdd(ref terms) => {
+                let command = [Command::MulAdd(terms.clone())];
+                op::<C, _>(move |p, input, output| {
+                    machine::step(&mut Running { prog: p, input, output, warnings: None }, &command, 0).map(|_| next)
+                })
+            },
+            Command::Out => op::<C, _>(move |p, _, output| {
+                p.write_cell(output)?;
+                p.outputs_written += 1;
+                Ok(next)
+            }),
+            Command::In => op::<C, _>(move |p, mut input, output| {
+                output.flush()?;
+                p.read_cell(&mut input)?;
+                Ok(next)
+            }),
+            // a jump lands on the matching bracket, which is skipped over
+            // as the interpreter does
+            Command::JmpFwd(target) => op::<C, _>(move |p, _, _| {
+                Ok(if p.memory[p.ptr].is_zero() { target + 1 } else { next })
+            }),
+            Command::JmpBack(target) => op::<C, _>(move |p, _, _| {
+                Ok(if p.memory[p.ptr].is_zero() { next } else { target + 1 })
+            }),
+            Command::Debug => op::<C, _>(move |p, _, output| {
+                output.flush()?;
+                p.debug(p.ptr, pc, None)?;
+                Ok(next)
+            }),
+        }
+    }).collect()
+}
+
+impl<C: Cell + 'static> Program<C> {
+    // run_jit runs the program like run but through closures built for each
+    // command, breakpoints, watchpoints, profiling and history are not supported
+    pub fn run_jit<R: Read, W: Write>(&mut self, input: &mut R, output: &mut W) -> Result<StepResult<C>, BfError> {
+        let ops = compile(&self.commands, self.checked_cells);
+        let mut output = BufWriter::new(output);
+        while self.pc < ops.len() {
+            self.steps += 1;
+            self.pc = ops[self.pc](self, input, &mut output).map_err(|e| self.locate(e))?;
+        }
+        output.flush()?;
+
+        Ok(StepResult::Halted)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use optimize;
+    use program::EofMode;
+    use std::io::empty;
+
+    // run commands through both the interpreter and the jit and check they
+    // print the same and leave the same tape
+    fn same_as_interpreter(commands: Vec<Command>, input: &[u8]) -> Vec<u8> {
+        let mut interpreted = Program::new(commands.clone());
+        let mut jitted = Program::new(commands);
+        interpreted.set_eof_mode(EofMode::Zero);
+        jitted.set_eof_mode(EofMode::Zero);
+
+        let expected = interpreted.run_to_vec(input).unwrap();
+        let mut output = Vec::new();
+        assert_eq!(jitted.run_jit(&mut &input[..], &mut output).unwrap(), StepResult::Halted);
+        assert_eq!(expected, output);
+        assert_eq!(interpreted.memory(), jitted.memory());
+        assert_eq!(interpreted.stats(), jitted.stats());
+        output
+    }
+
+    #[test]
+    fn hello_world() {
+        let raw = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+        let output = same_as_interpreter(Program::compile(raw, false), &[]);
+        assert_eq!(output, b"Hello World!\n");
+        same_as_interpreter(optimize::all(Program::compile(raw, false)), &[]);
+    }
+
+    #[test]
+    fn loops() {
+        // nested loops that count down from 16 three levels deep, then echo
+        let raw = "++++++++++++++++[>++++++++++++++++[>++++++++++++++++[-]<-]<-]>>>,[.,]";
+        let output = same_as_interpreter(Program::compile(raw, false), b"abc");
+        assert_eq!(output, b"abc");
+        same_as_interpreter(optimize::all(Program::compile(raw, false)), b"abc");
+    }
+
+    #[test]
+    fn errors() {
+        let mut prog = Program::from_str("+[<]");
+        match prog.run_jit(&mut empty(), &mut Vec::new()) {
+            Err(BfError::PointerUnderflow { pc: 2, .. }) => {},
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        let mut prog = Program::from_str("+>-");
+        prog.set_checked_cells(true);
+        match prog.run_jit(&mut empty(), &mut Vec::new()) {
+            Err(BfError::CellOverflow { pc: 2, ptr: 1, .. }) => {},
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+}
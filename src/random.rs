@@ -0,0 +1,57 @@
+use std::io::{self, Read};
+
+// RandomInput is an endless stream of pseudo-random bytes from an xorshift
+// generator, the same seed always gives the same bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RandomInput {
+    state: u64,
+}
+
+impl RandomInput {
+    pub fn new(seed: u64) -> RandomInput {
+        // xorshift gets stuck on a zero state so that seed is swapped out
+        let state = if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed };
+        RandomInput { state }
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 56) as u8
+    }
+}
+
+impl Read for RandomInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        for b in buf.iter_mut() {
+            *b = self.next_byte();
+        }
+        Ok(buf.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use program::Program;
+
+    fn bytes(seed: u64, n: usize) -> Vec<u8> {
+        let mut buf = vec![0; n];
+        RandomInput::new(seed).read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn deterministic() {
+        assert_eq!(bytes(42, 64), bytes(42, 64));
+        assert_ne!(bytes(42, 64), bytes(43, 64));
+        assert_ne!(bytes(0, 64), vec![0; 64]);
+
+        // a program sees the same bytes however they are read
+        let mut prog = Program::from_str(",.,.,.,.");
+        let mut output = Vec::new();
+        prog.run(&mut RandomInput::new(7), &mut output).unwrap();
+        assert_eq!(output, bytes(7, 4));
+    }
+}
@@ -0,0 +1,362 @@
+// Transpilers turn a compiled program into source code for another language
+// or format that does the same thing natively. The generated code does not check the
+// pointer against the bounds of the tape. Cells are stored unsigned as wrapping
+// works out the same, signed cells are only read as signed when printed.
+
+use std::fmt::Write;
+
+use command::Command;
+use program::{EofMode, OutputMode};
+
+// to_c emits a complete C program, cell_bits must be 8, 16 or 32
+pub fn to_c(commands: &[Command], tape_size: usize, cell_bits: u32, signed: bool, eof_mode: EofMode, output_mode: OutputMode) -> String {
+    let cell = format!("uint{}_t", cell_bits);
+    let shown = if signed { format!("(long long)(int{}_t)*ptr", cell_bits) } else { String::from("(long long)*ptr") };
+    let mut out = String::new();
+    writeln!(out, "#include <stdint.h>").unwrap();
+    writeln!(out, "#include <stdio.h>").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "static {} tape[{}];", cell, tape_size).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "int main(void) {{").unwrap();
+    writeln!(out, "    {} *ptr = tape;", cell).unwrap();
+    if commands.contains(&Command::In) {
+        writeln!(out, "    int c;").unwrap();
+    }
+
+    let mut depth = 1;
+    for command in commands {
+        if let Command::JmpBack(_) = *command {
+            depth -= 1;
+        }
+        let line = match *command {
+            Command::Right => String::from("++ptr;"),
+            Command::Left => String::from("--ptr;"),
+            Command::Inc => String::from("++*ptr;"),
+            Command::Dec => String::from("--*ptr;"),
+            Command::Add(n) if n < 0 => format!("*ptr -= {};", n.unsigned_abs()),
+            Command::Add(n) => format!("*ptr += {};", n),
+            Command::Move(n) if n < 0 => format!("ptr -= {};", n.unsigned_abs()),
+            Command::Move(n) => format!("ptr += {};", n),
+            Command::Clear => String::from("*ptr = 0;"),
+            Command::MulAdd(ref terms) => {
+                let mut line = String::from("if (*ptr) {");
+                for &(offset, factor) in terms {
+                    write!(line, " ptr[{}] += *ptr * {};", offset, factor).unwrap();
+                }
+                line.push_str(" *ptr = 0; }");
+                line
+            },
+            Command::Out => match output_mode {
+                OutputMode::Bytes => String::from("putchar(*ptr);"),
+                OutputMode::Decimal => format!("printf(\"%lld \", {});", shown),
+            },
+            Command::In => match eof_mode {
+                EofMode::Unchanged => String::from("if ((c = getchar()) != EOF) *ptr = c;"),
+                EofMode::Zero => String::from("*ptr = (c = getchar()) == EOF ? 0 : c;"),
+                EofMode::AllOnes => format!("*ptr = (c = getchar()) == EOF ? ({})-1 : c;", cell),
+            },
+            Command::JmpFwd(_) => String::from("while (*ptr) {"),
+            Command::JmpBack(_) => String::from("}"),
+            Command::Debug => format!("fprintf(stderr, \"PTR: %ld | CELL: %lld\\n\", (long)(ptr - tape), {});", shown),
+        };
+        writeln!(out, "{:width$}{}", "", line, width = depth * 4).unwrap();
+        if let Command::JmpFwd(_) = *command {
+            depth += 1;
+        }
+    }
+
+    writeln!(out, "    return 0;").unwrap();
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+// to_rust emits a complete Rust program with a fn main, using the same
+// wrapping cell arithmetic as the interpreter
+pub fn to_rust(commands: &[Command], tape_size: usize, cell_bits: u32, signed: bool, eof_mode: EofMode, output_mode: OutputMode) -> String {
+    let cell = format!("u{}", cell_bits);
+    let shown = if signed { format!("memory[ptr] as i{}", cell_bits) } else { String::from("memory[ptr]") };
+    let reads = commands.contains(&Command::In);
+    let writes = commands.contains(&Command::Out);
+    let mut out = String::new();
+    match (reads, writes) {
+        (true, true) => writeln!(out, "use std::io::{{self, Read, Write}};").unwrap(),
+        (true, false) => writeln!(out, "use std::io::{{self, Read}};").unwrap(),
+        (false, true) => writeln!(out, "use std::io::{{self, Write}};").unwrap(),
+        (false, false) => {},
+    }
+    writeln!(out).unwrap();
+    writeln!(out, "fn main() {{").unwrap();
+    // only what the program changes is declared mut, so the output compiles
+    // without warnings, and an empty program has no use for the tape at all
+    let moves = commands.iter().any(|c| matches!(*c, Command::Right | Command::Left | Command::Move(_)));
+    let changes = commands.iter().any(|c| matches!(*c, Command::Inc | Command::Dec | Command::Add(_)
+        | Command::Clear | Command::MulAdd(_) | Command::In));
+    if !commands.is_empty() {
+        writeln!(out, "    let {}memory = vec![0{}; {}];", if changes { "mut " } else { "" }, cell, tape_size).unwrap();
+        writeln!(out, "    let {}ptr: usize = 0;", if moves { "mut " } else { "" }).unwrap();
+    }
+    if reads {
+        writeln!(out, "    let stdin = io::stdin();").unwrap();
+        writeln!(out, "    let mut input = stdin.lock().bytes();").unwrap();
+    }
+    if writes {
+        writeln!(out, "    let stdout = io::stdout();").unwrap();
+        writeln!(out, "    let mut output = io::BufWriter::new(stdout.lock());").unwrap();
+    }
+
+    let modulus = 1i64 << cell_bits;
+    let mut depth = 1;
+    for command in commands {
+        if let Command::JmpBack(_) = *command {
+            depth -= 1;
+        }
+        let line = match *command {
+            Command::Right => String::from("ptr += 1;"),
+            Command::Left => String::from("ptr -= 1;"),
+            Command::Inc => String::from("memory[ptr] = memory[ptr].wrapping_add(1);"),
+            Command::Dec => String::from("memory[ptr] = memory[ptr].wrapping_sub(1);"),
+            Command::Add(n) => format!("memory[ptr] = memory[ptr].wrapping_add({});", i64::from(n).rem_euclid(modulus)),
+            Command::Move(n) if n < 0 => format!("ptr -= {};", n.unsigned_abs()),
+            Command::Move(n) => format!("ptr += {};", n),
+            Command::Clear => String::from("memory[ptr] = 0;"),
+            Command::MulAdd(ref terms) => {
+                let mut line = String::from("if memory[ptr] != 0 { let v = memory[ptr];");
+                for &(offset, factor) in terms {
+                    let cell = if offset < 0 {
+                        format!("memory[ptr - {}]", offset.unsigned_abs())
+                    } else {
+                        format!("memory[ptr + {}]", offset)
+                    };
+                    write!(line, " {} = {}.wrapping_add(v.wrapping_mul({}));",
+                        cell, cell, i64::from(factor).rem_euclid(modulus)).unwrap();
+                }
+                line.push_str(" memory[ptr] = 0; }");
+                line
+            },
+            Command::Out => match output_mode {
+                OutputMode::Bytes => String::from("output.write_all(&[memory[ptr] as u8]).unwrap();"),
+                OutputMode::Decimal => format!("write!(output, \"{{}} \", {}).unwrap();", shown),
+            },
+            Command::In => {
+                let eof = match eof_mode {
+                    EofMode::Unchanged => String::from("{}"),
+                    EofMode::Zero => String::from("memory[ptr] = 0"),
+                    EofMode::AllOnes => format!("memory[ptr] = {}::MAX", cell),
+                };
+                if writes {
+                    // make sure a prompt is visible before blocking on input
+                    writeln!(out, "{:width$}output.flush().unwrap();", "", width = depth * 4).unwrap();
+                }
+                format!("match input.next() {{ Some(b) => memory[ptr] = {}::from(b.unwrap()), None => {} }}", cell, eof)
+            },
+            Command::JmpFwd(_) => String::from("while memory[ptr] != 0 {"),
+            Command::JmpBack(_) => String::from("}"),
+            Command::Debug => format!("eprintln!(\"PTR: {{}} | CELL: {{}}\", ptr, {});", shown),
+        };
+        writeln!(out, "{:width$}{}", "", line, width = depth * 4).unwrap();
+        if let Command::JmpFwd(_) = *command {
+            depth += 1;
+        }
+    }
+
+    if writes {
+        writeln!(out, "    output.flush().unwrap();").unwrap();
+    }
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+// to_wat emits a WebAssembly text module exporting the tape as "memory" and
+// the program as a "run" function. Cells are stored little endian at their
+// index times their width in bytes. What the program uses of these functions
+// is imported from "env":
+//   output(byte) is given each cell written by '.' truncated to a byte,
+//   output_number(value) is given the value of the cell instead when the
+//   output mode is decimal, and should print it followed by a space,
+//   input() returns the next byte of input or -1 at the end of input,
+//   debug(ptr, cell) is called for each '#'
+pub fn to_wat(commands: &[Command], tape_size: usize, cell_bits: u32, signed: bool, eof_mode: EofMode, output_mode: OutputMode) -> String {
+    let width = cell_bits as usize / 8;
+    let (load, store) = match cell_bits {
+        8 => ("i32.load8_u", "i32.store8"),
+        16 => ("i32.load16_u", "i32.store16"),
+        _ => ("i32.load", "i32.store"),
+    };
+    // the value of the current cell as it is printed
+    let shown = match (cell_bits, signed) {
+        (8, true) => String::from("(i32.load8_s (local.get $ptr))"),
+        (16, true) => String::from("(i32.load16_s (local.get $ptr))"),
+        _ => format!("({} (local.get $ptr))", load),
+    };
+    // the address of the cell offset cells from the pointer
+    let address = |offset: isize| if offset == 0 {
+        String::from("(local.get $ptr)")
+    } else {
+        format!("(i32.add (local.get $ptr) (i32.const {}))", offset * width as isize)
+    };
+    let cell = format!("({} (local.get $ptr))", load);
+
+    let mut out = String::new();
+    writeln!(out, "(module").unwrap();
+    if commands.contains(&Command::Out) && output_mode == OutputMode::Decimal {
+        writeln!(out, "  (import \"env\" \"output_number\" (func $output_number (param i64)))").unwrap();
+    } else if commands.contains(&Command::Out) {
+        writeln!(out, "  (import \"env\" \"output\" (func $output (param i32)))").unwrap();
+    }
+    if commands.contains(&Command::In) {
+        writeln!(out, "  (import \"env\" \"input\" (func $input (result i32)))").unwrap();
+    }
+    if commands.contains(&Command::Debug) {
+        writeln!(out, "  (import \"env\" \"debug\" (func $debug (param i32 i32)))").unwrap();
+    }
+    let pages = usize::max(1, (tape_size * width).div_ceil(65536));
+    writeln!(out, "  (memory (export \"memory\") {})", pages).unwrap();
+    writeln!(out, "  (func (export \"run\")").unwrap();
+    writeln!(out, "    (local $ptr i32) (local $v i32)").unwrap();
+
+    let mut depth = 2;
+    for command in commands {
+        if let Command::JmpBack(_) = *command {
+            depth -= 1;
+        }
+        let line = match *command {
+            Command::Right => format!("(local.set $ptr {})", address(1)),
+            Command::Left => format!("(local.set $ptr {})", address(-1)),
+            Command::Move(n) => format!("(local.set $ptr {})", address(n)),
+            Command::Inc => format!("({} (local.get $ptr) (i32.add {} (i32.const 1)))", store, cell),
+            Command::Dec => format!("({} (local.get $ptr) (i32.sub {} (i32.const 1)))", store, cell),
+            Command::Add(n) => format!("({} (local.get $ptr) (i32.add {} (i32.const {})))", store, cell, n),
+            Command::Clear => format!("({} (local.get $ptr) (i32.const 0))", store),
+            Command::MulAdd(ref terms) => {
+                // the cells either side are only touched when the loop would run
+                let mut line = format!("(local.set $v {}) (if (local.get $v) (then", cell);
+                for &(offset, factor) in terms {
+                    write!(line, " ({} {} (i32.add ({} {}) (i32.mul (local.get $v) (i32.const {}))))",
+                        store, address(offset), load, address(offset), factor).unwrap();
+                }
+                write!(line, " ({} (local.get $ptr) (i32.const 0))))", store).unwrap();
+                line
+            },
+            Command::Out if output_mode == OutputMode::Decimal => {
+                format!("(call $output_number (i64.extend_i32_{} {}))", if signed { "s" } else { "u" }, shown)
+            },
+            Command::Out if cell_bits > 8 => format!("(call $output (i32.and {} (i32.const 255)))", cell),
+            Command::Out => format!("(call $output {})", cell),
+            Command::In => {
+                let eof = match eof_mode {
+                    EofMode::Unchanged => String::new(),
+                    EofMode::Zero => format!(" (else ({} (local.get $ptr) (i32.const 0)))", store),
+                    EofMode::AllOnes => format!(" (else ({} (local.get $ptr) (i32.const -1)))", store),
+                };
+                format!("(local.set $v (call $input)) (if (i32.ge_s (local.get $v) (i32.const 0)) (then ({} (local.get $ptr) (local.get $v))){})",
+                    store, eof)
+            },
+            Command::JmpFwd(_) => format!("(block (loop (br_if 1 (i32.eqz {}))", cell),
+            Command::JmpBack(_) => String::from("(br 0)))"),
+            Command::Debug => format!("(call $debug (i32.div_u (local.get $ptr) (i32.const {})) {})", width, shown),
+        };
+        writeln!(out, "{:width$}{}", "", line, width = depth * 2).unwrap();
+        if let Command::JmpFwd(_) = *command {
+            depth += 1;
+        }
+    }
+
+    writeln!(out, "  )").unwrap();
+    writeln!(out, ")").unwrap();
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use program::{OutputMode, Program};
+
+    // check that every brace is closed in the right order
+    fn balanced(source: &str) -> bool {
+        let mut depth = 0;
+        for c in source.chars() {
+            match c {
+                '{' => depth += 1,
+                '}' if depth == 0 => return false,
+                '}' => depth -= 1,
+                _ => {},
+            }
+        }
+        depth == 0
+    }
+
+    #[test]
+    fn hello_world_c() {
+        let raw = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+        let c = Program::from_str(raw).to_c();
+
+        assert!(c.starts_with("#include <stdint.h>\n#include <stdio.h>\n"));
+        assert!(c.contains("static uint8_t tape[30000];"));
+        assert_eq!(raw.matches('.').count(), c.matches("putchar(*ptr);").count());
+        assert_eq!(c.matches('{').count(), c.matches('}').count());
+        assert!(c.contains("\n    while (*ptr) {\n        ++ptr;\n"));
+        assert!(c.contains("\n        while (*ptr) {\n            ++ptr;\n"));
+    }
+
+    #[test]
+    fn hello_world_rust() {
+        let raw = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+        let rust = Program::from_str(raw).to_rust();
+
+        assert!(rust.contains("fn main() {\n    let mut memory = vec![0u8; 30000];\n"));
+        assert!(balanced(&rust));
+        assert_eq!(raw.matches('[').count(), rust.matches("while memory[ptr] != 0 {").count());
+        assert_eq!(raw.matches('.').count(), rust.matches("output.write_all(").count());
+        // nothing is mut that the program doesn't change
+        let rust = Program::from_str("+.").to_rust();
+        assert!(rust.contains("    let mut memory = vec![0u8; 30000];\n    let ptr: usize = 0;\n"));
+        let rust = Program::from_str(">.").to_rust();
+        assert!(rust.contains("    let memory = vec![0u8; 30000];\n    let mut ptr: usize = 0;\n"));
+        assert_eq!(Program::from_str("").to_rust(), "\nfn main() {\n}\n");
+    }
+
+    #[test]
+    fn hello_world_wat() {
+        let raw = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+        let wat = Program::from_str(raw).to_wat();
+
+        assert!(wat.starts_with("(module\n  (import \"env\" \"output\" (func $output (param i32)))\n"));
+        assert!(wat.contains("(memory (export \"memory\") 1)"));
+        let mut depth = 0;
+        for c in wat.chars() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {},
+            }
+            assert!(depth >= 0);
+        }
+        assert_eq!(depth, 0);
+        assert_eq!(raw.matches('[').count(), wat.matches("(block (loop").count());
+        assert_eq!(raw.matches(']').count(), wat.matches("(br 0)))").count());
+        assert_eq!(raw.matches('.').count(), wat.matches("(call $output").count());
+        assert!(!wat.contains("$input"));
+    }
+
+    #[test]
+    fn decimal_output() {
+        // signed cells are printed as signed, as the interpreter does
+        let mut prog: Program<i8> = Program::with_cells(Program::compile("-.#", true));
+        prog.set_output_mode(OutputMode::Decimal);
+        let c = prog.to_c();
+        assert!(c.contains("static uint8_t tape[30000];"));
+        assert!(c.contains("printf(\"%lld \", (long long)(int8_t)*ptr);"));
+        assert!(c.contains("(long)(ptr - tape), (long long)(int8_t)*ptr);"));
+        assert!(prog.to_rust().contains("write!(output, \"{} \", memory[ptr] as i8).unwrap();"));
+        let wat = prog.to_wat();
+        assert!(wat.contains("(import \"env\" \"output_number\" (func $output_number (param i64)))"));
+        assert!(wat.contains("(call $output_number (i64.extend_i32_s (i32.load8_s (local.get $ptr))))"));
+        assert!(!wat.contains("$output "));
+
+        let mut prog: Program<u16> = Program::with_cells(Program::compile("-.", false));
+        prog.set_output_mode(OutputMode::Decimal);
+        assert!(prog.to_c().contains("printf(\"%lld \", (long long)*ptr);"));
+        assert!(prog.to_rust().contains("write!(output, \"{} \", memory[ptr]).unwrap();"));
+        assert!(prog.to_wat().contains("(call $output_number (i64.extend_i32_u (i32.load16_u (local.get $ptr))))"));
+    }
+}
@@ -0,0 +1,501 @@
+#![cfg(feature = "std")]
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+// write source to a file of its own and run the interpreter on it with no input
+fn run<S: AsRef<[u8]>>(name: &str, source: S, args: &[&str]) -> Output {
+    let path = source_file(name, source);
+    let output = Command::new(env!("CARGO_BIN_EXE_brainfuck"))
+        .args(args)
+        .arg(&path)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+    output
+}
+
+// like run but with input written to stdin
+fn run_with_stdin<S: AsRef<[u8]>>(name: &str, source: S, input: &[u8], args: &[&str]) -> Output {
+    let path = source_file(name, source);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_brainfuck"))
+        .args(args)
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    fs::remove_file(&path).unwrap();
+    output
+}
+
+fn source_file<S: AsRef<[u8]>>(name: &str, source: S) -> PathBuf {
+    let path = env::temp_dir().join(format!("brainfuck-cli-{}-{}.b", name, std::process::id()));
+    fs::write(&path, source).unwrap();
+    path
+}
+
+#[test]
+fn hello_world() {
+    let raw = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+    let output = run("hello", raw, &[]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"Hello World!\n");
+}
+
+#[test]
+fn minify() {
+    let raw = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+    let commented = "+++++ +++               Set Cell #0 to 8
+[
+    >++++               Add 4 to Cell #1
+    [
+        >++             Add 4*2 to Cell #2
+        >+++            Add 4*3 to Cell #3
+        >+++            Add 4*3 to Cell #4
+        >+              Add 4 to Cell #5
+        <<<<-           Decrement the loop counter in Cell #1
+    ]                   Loop till Cell #1 is zero
+    >+ >+ >- >>+        Add to Cells #2 #3 and #5 and take 1 from Cell #4
+    [<]                 Move back to the first zero cell
+    <-                  Decrement the loop counter in Cell #0
+]                       Loop till Cell #0 is zero
+>>.                     'H'
+>---.                   'e'
++++++++..+++.           'llo'
+>>.                     space
+<-.<.+++.------.--------.   'World'
+>>+.>++.                '!' and a newline
+";
+    let output = run("minify", commented, &["--minify"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), format!("{}\n", raw));
+
+    // and the minified program runs the same
+    let output = run("minified", &output.stdout, &[]);
+    assert_eq!(output.stdout, b"Hello World!\n");
+}
+
+#[test]
+fn optimize_source() {
+    let output = run("optimize-source-empty", "++--", &["--optimize-source"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"\n");
+
+    let raw = "[dead.]+++>><<>+++[->++<<>]>[+]<<.";
+    let output = run("optimize-source", raw, &["--optimize-source"]);
+    let optimized = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(optimized, "+++>+++[->++<]>[-]<<.\n");
+    assert!(optimized.len() < raw.len());
+    assert_eq!(run("optimized", &optimized, &[]).stdout, run("unoptimized", raw, &[]).stdout);
+}
+
+#[test]
+fn unmatched_brackets() {
+    let output = run("unmatched-open", "[[]", &[]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "error: unmatched '[' at line 1, column 1\n");
+
+    let output = run("unmatched-close", "+\n  ]", &[]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "error: unmatched ']' at line 2, column 3\n");
+
+    // every unmatched bracket is reported in source order
+    let output = run("unmatched-many", "]\n[[]\n]]", &[]);
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "error: unmatched ']' at line 1, column 1\n\
+        error: unmatched ']' at line 3, column 2\n");
+}
+
+#[test]
+fn dump_tokens() {
+    let output = run("dump-tokens", ",[.,]", &["--dump-tokens"]);
+    assert!(output.status.success());
+    let listing = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!(lines, vec!["0 In", "1 JmpFwd 4", "2 Out", "3 In", "4 JmpBack 1"]);
+
+    let output = run("dump-tokens-optimized", "+++[-]>>", &["--dump-tokens", "-O"]);
+    assert_eq!(output.stdout, b"0 Add 3\n1 Clear\n2 Move 2\n");
+}
+
+#[test]
+fn explain() {
+    let output = run("explain", "+[>.<-]", &["--explain"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "0 +: increment the current cell\n\
+        1 [: jump past the matching ] at 6 if the current cell is 0\n\
+        2 >: move the pointer right\n\
+        3 .: output the current cell\n\
+        4 <: move the pointer left\n\
+        5 -: decrement the current cell\n\
+        6 ]: jump back to the matching [ at 1 unless the current cell is 0\n");
+}
+
+#[test]
+fn custom_commands() {
+    let map = source_file("commands", "i +\nr >\no .\n\n( [\n) ]\nd -\nl <\n");
+    let path = map.to_str().unwrap();
+    let output = run("remapped", "iiiiiiii(riiiiiiiild)ro", &["--commands", path]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"@");
+
+    // the standard characters are comments once the commands are remapped
+    let output = run("remapped-comments", "+++.", &["--commands", path]);
+    assert_eq!(output.stdout, b"");
+
+    let bad = source_file("commands-bad", "i +\nx y\n");
+    let output = run("remapped-bad", "i", &["--commands", bad.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "error: line 2 of the commands file should be a character and a command\n");
+    fs::remove_file(&map).unwrap();
+    fs::remove_file(&bad).unwrap();
+}
+
+#[test]
+fn ook() {
+    let raw = "Ook. Ook. Ook. Ook. Ook! Ook? Ook. Ook? Ook. Ook. Ook. Ook. Ook. Ook. Ook? Ook. Ook! Ook! Ook? Ook! \
+        Ook. Ook? Ook! Ook.";
+    let output = run("ook", raw, &["--lang", "ook"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"\x06");
+}
+
+#[test]
+fn repeat() {
+    let output = run("repeat", "++++++++[>++++++++<-]>+.+.+.", &["--repeat", "3"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"ABCABCABC");
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("ran 3 times in "));
+}
+
+#[test]
+fn warnings() {
+    let output = run("warn", "[comment.]+\n+[]", &["--warn", "--max-steps", "100"]);
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "warning: loop is never entered at line 1, column 1\n\
+        warning: empty loop never ends once entered at line 2, column 2\n\
+        error: step limit of 100 exceeded at pc 6\n");
+
+    let output = run("warn-tape", ">>+\n>>", &["--warn", "--tape-size", "4"]);
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "warning: pointer moves past the end of the tape at line 2, column 2\n\
+        error: tape overflow at pc 4: pointer 4 (line 2, column 2)\n");
+    let output = run("warn-growable-tape", ">>+\n>>", &["--warn", "--tape-size", "4", "--tape-mode", "growable"]);
+    assert_eq!(output.stderr, b"");
+
+    // dead code only goes away when asked
+    let output = run("dead-code", "[.]+.", &["--dump-tokens"]);
+    assert_eq!(output.stdout, b"0 JmpFwd 2\n1 Out\n2 JmpBack 0\n3 Inc\n4 Out\n");
+    let output = run("skip-dead-code", "[.]+.", &["--dump-tokens", "--skip-dead-code"]);
+    assert_eq!(output.stdout, b"0 Inc\n1 Out\n");
+}
+
+#[test]
+fn debug_positions() {
+    let output = run("debug-positions", "+\n  >#", &["-d"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("PC: 2 | PTR: 1 | LINE: 2 | COLUMN: 4\n"), "{}", stderr);
+}
+
+#[test]
+fn debug_file() {
+    let path = env::temp_dir().join(format!("brainfuck-cli-debug-file-{}.txt", std::process::id()));
+    let output = run("debug-file", "+.#.", &["-d", "--debug-file", path.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, vec![1, 1]);
+    assert_eq!(output.stderr, b"");
+    let debug = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(debug.starts_with("--------------------------\nPC: 2 | PTR: 0 | LINE: 1 | COLUMN: 3\n"), "{}", debug);
+}
+
+#[test]
+fn decimal_output() {
+    let raw = "++++++++[>+++++++++<-]>.<+++[>+++++++++++<-]>.";
+    let output = run("decimal", raw, &["--output-mode", "decimal"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"72 105 ");
+}
+
+#[test]
+fn echo_input() {
+    // upper case each letter, the echo shows what was typed before each result
+    let raw = ",[>++++[<-------->-]<.,]";
+    let output = run_with_stdin("echo-input", raw, b"abc", &["--echo-input", "--eof", "zero"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"aAbBcC");
+
+    let output = run_with_stdin("no-echo-input", raw, b"abc", &["--eof", "zero"]);
+    assert_eq!(output.stdout, b"ABC");
+}
+
+#[test]
+fn invalid_utf8() {
+    let output = run("invalid-utf8", &b"\xff+++\xc3\x28[>++<-]>."[..], &[]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, vec![6]);
+}
+
+#[test]
+fn comment_regions() {
+    let raw = "+++{ disabled: [-] }.";
+    assert_eq!(run("comment-regions", raw, &["--comment-regions"]).stdout, vec![3]);
+    assert_eq!(run("no-comment-regions", raw, &[]).stdout, vec![0]);
+}
+
+#[test]
+fn strict_parse() {
+    let raw = "+++\n +x.";
+    let output = run("strict-parse", raw, &["--strict-parse"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "error: unexpected 'x' at line 2, column 3\n");
+    assert_eq!(output.stdout, b"");
+
+    let output = run("lenient-parse", raw, &[]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, vec![4]);
+}
+
+#[test]
+fn visualize() {
+    let output = run("visualize", "++++++++[>++++++++<-]>+.+.", &["--visualize"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"AB");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "0: 0 [65] 0 0 0 0\n0: 0 [66] 0 0 0 0\n");
+}
+
+#[test]
+fn diagnostics_in_order() {
+    let output = run("diagnostics-order", "<+.#", &["--trace", "--lenient", "--visualize", "-d", "--tape-size", "3"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let lines: Vec<&str> = stderr.lines().take(7).collect();
+    assert_eq!(lines, vec![
+        "pc=0 ptr=0 cell=0 Left",
+        "warning: tape underflow at pc 0, keeping the pointer at cell 0",
+        "pc=1 ptr=0 cell=0 Inc",
+        "pc=2 ptr=0 cell=1 Out",
+        "0: [1] 0 0",
+        "pc=3 ptr=0 cell=1 Debug",
+        "--------------------------",
+    ]);
+}
+
+#[test]
+fn random_input() {
+    let raw = ",.,.,.,.,.,.,.,.";
+    let first = run("random-input-1", raw, &["--random-input", "1234"]);
+    let second = run("random-input-2", raw, &["--random-input", "1234"]);
+    assert!(first.status.success());
+    assert_eq!(first.stdout.len(), 8);
+    assert_eq!(first.stdout, second.stdout);
+
+    let other = run("random-input-3", raw, &["--random-input", "4321"]);
+    assert_ne!(first.stdout, other.stdout);
+}
+
+#[test]
+fn checked_cells() {
+    let output = run("checked-cells", "+.>-.", &["--checked-cells"]);
+    assert_eq!(output.stdout, vec![1]);
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "error: cell 1 overflowed at pc 3 (line 1, column 4)\n");
+
+    let output = run("unchecked-cells", "+.>-.", &[]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, vec![1, 255]);
+}
+
+#[test]
+fn lenient() {
+    let output = run("strict-moves", "<+++.", &[]);
+    assert!(output.stdout.is_empty());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "error: tape underflow at pc 0 (line 1, column 1)\n");
+
+    let output = run("lenient-moves", "<+++.", &["--lenient"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, vec![3]);
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "warning: tape underflow at pc 0, keeping the pointer at cell 0\n");
+}
+
+#[test]
+fn compare() {
+    let raw = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+    let commented = r" 1 +++++ +++               Set Cell #0 to 8
+ 2 [
+ 3     >++++               Add 4 to Cell #1; this will always set Cell #1 to 4
+ 4     [                   as the cell will be cleared by the loop
+ 5         >++             Add 4*2 to Cell #2
+ 6         >+++            Add 4*3 to Cell #3
+ 7         >+++            Add 4*3 to Cell #4
+ 8         >+              Add 4 to Cell #5
+ 9         <<<<-           Decrement the loop counter in Cell #1
+10     ]                   Loop till Cell #1 is zero
+11     >+                  Add 1 to Cell #2
+12     >+                  Add 1 to Cell #3
+13     >-                  Subtract 1 from Cell #4
+14     >>+                 Add 1 to Cell #6
+15     [<]                 Move back to the first zero cell you find; this will
+16                         be Cell #1 which was cleared by the previous loop
+17     <-                  Decrement the loop Counter in Cell #0
+18 ]                       Loop till Cell #0 is zero
+19 
+20 The result of this is:
+21 Cell No :   0   1   2   3   4   5   6
+22 Contents:   0   0  72 104  88  32   8
+23 Pointer :   ^
+24 
+25 >>.                     Cell #2 has value 72 which is 'H'
+26 >---.                   Subtract 3 from Cell #3 to get 101 which is 'e'
+27 +++++ ++..+++.          Likewise for 'llo' from Cell #3
+28 >>.                     Cell #5 is 32 for the space
+29 <-.                     Subtract 1 from Cell #4 for 87 to give a 'W'
+30 <.                      Cell #3 was set to 'o' from the end of 'Hello'
+31 +++.----- -.----- ---.  Cell #3 for 'rl' and 'd'
+32 >>+.                    Add 1 to Cell #5 gives us an exclamation point
+33 >++.                    And finally a newline from Cell #6";
+    let path = source_file("compare-commented", commented);
+    let output = run("compare-raw", raw, &["--compare", path.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "outputs match (13 bytes)\n");
+
+    let other = source_file("compare-other", "+++.+.");
+    let output = run("compare-shorter", "+++.++.+.", &["--compare", other.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout),
+        "byte 1: 5 != 4\nlengths differ: PROGRAM wrote 3 bytes, the second program wrote 2\noutputs differ\n");
+
+    // the second program is read and run with the same settings as PROGRAM
+    let regions = source_file("compare-regions", "{+}++.");
+    let output = run("compare-regions-raw", "++.", &["--comment-regions", "--compare", regions.to_str().unwrap()]);
+    assert!(output.status.success());
+    let output = run("compare-strict", "++.", &["--strict-parse", "--compare", regions.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "error: unexpected '{' at line 1, column 1, unexpected '}' at line 1, column 3\n");
+    // prints 1 only when a cell can hold 256
+    let wide = source_file("compare-wide", "++++++++[>++++++++++++++++++++++++++++++++<-]>[[-]<+>]<.");
+    let output = run("compare-wide-raw", "+.", &["--cell-size", "16", "--compare", wide.to_str().unwrap()]);
+    assert!(output.status.success());
+    let output = run("compare-narrow-raw", "+.", &["--compare", wide.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    // lenient mode warnings still reach stderr when comparing
+    let output = run("compare-lenient", "<+.", &["--lenient", "--compare", other.to_str().unwrap()]);
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "warning: tape underflow at pc 0, keeping the pointer at cell 0\n");
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(&other).unwrap();
+    fs::remove_file(&regions).unwrap();
+    fs::remove_file(&wide).unwrap();
+}
+
+#[test]
+fn histogram() {
+    let output = run("histogram", "++>+>>-", &["--histogram", "--tape-size", "5"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), format!("2 zero cells, 3 nonzero cells\n{:>12} {:>12}\n{:>12} {:>12}\n{:>12} {:>12}\n",
+        1, 1, 2, 1, 255, 1));
+}
+
+#[test]
+fn annotate_output() {
+    let output = run("annotate-output", "++++++++[>++++++++<-]>+.\n+.", &["--annotate-output"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "pc=23 line=1 column=24 byte=65 'A'\npc=25 line=2 column=2 byte=66 'B'\n");
+}
+
+#[test]
+fn line_input() {
+    let output = run_with_stdin("line-input", ",[.,]", b"one\ntwo\n", &["--line-input", "--eof", "zero"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"one\ntwo\n");
+}
+
+#[test]
+fn run_directory() {
+    let dir = env::temp_dir().join(format!("brainfuck-cli-directory-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("echo.b"), ",[.,]").unwrap();
+    fs::write(dir.join("echo.in"), "abc").unwrap();
+    fs::write(dir.join("echo.out"), "abc").unwrap();
+    fs::write(dir.join("wrong.bf"), "+++.").unwrap();
+    fs::write(dir.join("wrong.out"), [2]).unwrap();
+    fs::write(dir.join("unchecked.b"), "+.").unwrap();
+    fs::write(dir.join("notes.txt"), "not a program").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_brainfuck"))
+        .args(["--eof", "zero"])
+        .arg(&dir)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "PASS echo.b\n\
+        SKIP unchecked.b: no expected output\n\
+        FAIL wrong.bf: output differs\n\
+        1 passed, 1 failed\n");
+}
+
+#[test]
+fn max_output() {
+    let output = run("max-output", "++++++++[>++++++++<-]>[.]", &["--max-output", "3"]);
+    assert_eq!(output.stdout, b"@@@");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "error: output limit of 3 exceeded at pc 23\n");
+}
+
+#[test]
+fn includes() {
+    let dir = env::temp_dir().join(format!("brainfuck-cli-includes-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("main.b"), "++++++++[>++++++++<-]>\n@include inc.b\n.").unwrap();
+    fs::write(dir.join("inc.b"), "+").unwrap();
+    fs::write(dir.join("self.b"), "@include self.b").unwrap();
+
+    let run_file = |name: &str, args: &[&str]| Command::new(env!("CARGO_BIN_EXE_brainfuck"))
+        .args(args)
+        .arg(dir.join(name))
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    let included = run_file("main.b", &["--includes"]);
+    let plain = run_file("main.b", &[]);
+    let cycle = run_file("self.b", &["--includes"]);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(included.stdout, b"A");
+    // without the flag the directive is a comment, except for the '.' in the name
+    assert_eq!(plain.stdout, b"@@");
+    assert_eq!(cycle.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&cycle.stderr).ends_with("self.b includes itself\n"));
+}
+
+#[test]
+fn exit_status() {
+    let output = run("error-status", "+.<", &[]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stdout, vec![1]);
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "error: tape underflow at pc 2 (line 1, column 3)\n");
+
+    let output = run("quiet", "+.<", &["--quiet"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stdout, vec![1]);
+    assert_eq!(output.stderr, b"");
+
+    assert_eq!(run("success-status", "+.", &["-q"]).status.code(), Some(0));
+}
+
+#[test]
+fn emit() {
+    let output = run("emit", "+.", &["--emit", "c"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("putchar(*ptr);"));
+
+    // the translations can't wrap or grow the tape
+    let output = run("emit-wrapping", "<+.", &["--tape-mode", "wrapping", "--emit", "c"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "error: --emit only supports the fixed tape mode\n");
+}